@@ -24,6 +24,18 @@ static SOS: u8 = 0xDA;
 static DQT: u8 = 0xDB;
 //Application segments start and end
 static APP0: u8 = 0xE0;
+//ICC profile application segment
+static APP2: u8 = 0xE2;
+//Define restart interval
+static DRI: u8 = 0xDD;
+//First of the eight restart markers (RST0..RST7)
+static RST0: u8 = 0xD0;
+//Adobe application segment (colour transform convention)
+static APP14: u8 = 0xEE;
+
+//The most profile bytes one APP2 segment can carry after its 14-byte header.
+static ICC_MAX_CHUNK: uint = 65519;
+static ICC_MARKER: &'static [u8] = bytes!("ICC_PROFILE\0");
 
 //section K.1
 //table K.1
@@ -121,6 +133,89 @@ static CHROMADESTINATION: u8 = 1;
 static LUMAID: u8 = 1;
 static CHROMABLUEID: u8 = 2;
 static CHROMAREDID: u8 = 3;
+static KEYID: u8 = 4;
+
+//Adobe APP14 colour transform codes.
+static ADOBE_TRANSFORM_CMYK: u8 = 0;
+static ADOBE_TRANSFORM_YCCK: u8 = 2;
+
+/// The chroma subsampling ratio used when encoding color images.
+pub enum SamplingFactor {
+	/// 4:4:4 — full chroma resolution (luma 1x1).
+	Sampling444,
+
+	/// 4:2:2 — chroma halved horizontally (luma 2x1).
+	Sampling422,
+
+	/// 4:2:0 — chroma halved in both directions (luma 2x2).
+	Sampling420,
+}
+
+/// A source of 8-bit pixels the JPEG encoder pulls ```8x8``` blocks from on demand.
+///
+/// Implementing this rather than handing ```encode``` a packed RGB buffer lets large
+/// images and grayscale/RGBA inputs be encoded without first materialising an RGB
+/// copy in memory. ```pixel``` is expected to return the pixel as an 8-bit RGB triple;
+/// grayscale sources simply return the same value on all three channels so the inline
+/// ```rgb_to_ycbcr``` conversion yields ```Cb = Cr = 128```.
+pub trait PixelSource {
+	/// The ```(width, height)``` of the source in pixels.
+	fn dimensions(&self) -> (uint, uint);
+
+	/// The number of components to encode: 1 for grayscale, 3 for color.
+	fn components(&self) -> uint;
+
+	/// The pixel at ```(x, y)``` as an 8-bit RGB triple.
+	fn pixel(&self, x: uint, y: uint) -> (u8, u8, u8);
+
+	/// The fourth (key) channel at ```(x, y)```, as used by CMYK/YCCK sources.
+	/// Sources without a fourth channel return ```0```.
+	fn key(&self, _x: uint, _y: uint) -> u8 {
+		0
+	}
+}
+
+/// Adapts a packed byte buffer (as produced by ```raw_pixels```) to a ```PixelSource```.
+struct ByteSource<'a> {
+	buf: &'a [u8],
+	width: uint,
+	height: uint,
+	bpp: uint,
+	grey: bool,
+}
+
+impl<'a> PixelSource for ByteSource<'a> {
+	fn dimensions(&self) -> (uint, uint) {
+		(self.width, self.height)
+	}
+
+	fn components(&self) -> uint {
+		if self.grey {1} else {3}
+	}
+
+	fn pixel(&self, x: uint, y: uint) -> (u8, u8, u8) {
+		//Replicate the last valid column/row for blocks past the border.
+		let x = if x >= self.width  {self.width  - 1} else {x};
+		let y = if y >= self.height {self.height - 1} else {y};
+
+		let i = (y * self.width + x) * self.bpp;
+
+		if self.grey {
+			let v = self.buf[i];
+			(v, v, v)
+		} else {
+			(self.buf[i], self.buf[i + 1], self.buf[i + 2])
+		}
+	}
+
+	fn key(&self, x: uint, y: uint) -> u8 {
+		let x = if x >= self.width  {self.width  - 1} else {x};
+		let y = if y >= self.height {self.height - 1} else {y};
+
+		let i = (y * self.width + x) * self.bpp;
+		self.buf[i + 3]
+	}
+}
 
 /// The representation of a JPEG encoder
 pub struct JPEGEncoder<W> {
@@ -136,6 +231,30 @@ pub struct JPEGEncoder<W> {
 	luma_actable: Vec<(u8, u16)>,
 	chroma_dctable: Vec<(u8, u16)>,
 	chroma_actable: Vec<(u8, u16)>,
+
+	optimize: bool,
+	icc_profile: Option<Vec<u8>>,
+	restart_interval: Option<u16>,
+}
+
+//The symbol frequencies gathered by the counting pass, one pair of ```freq[257]```
+//arrays per Huffman table. Chroma counts stay zero for grayscale encodes.
+struct Freqs {
+	luma_dc: Vec<u32>,
+	luma_ac: Vec<u32>,
+	chroma_dc: Vec<u32>,
+	chroma_ac: Vec<u32>,
+}
+
+impl Freqs {
+	fn new() -> Freqs {
+		Freqs {
+			luma_dc:   Vec::from_elem(257, 0u32),
+			luma_ac:   Vec::from_elem(257, 0u32),
+			chroma_dc: Vec::from_elem(257, 0u32),
+			chroma_ac: Vec::from_elem(257, 0u32),
+		}
+	}
 }
 
 impl<W: Writer> JPEGEncoder<W> {
@@ -169,13 +288,126 @@ impl<W: Writer> JPEGEncoder<W> {
 
 			accumulator: 0,
 			nbits: 0,
+
+			optimize: false,
+			icc_profile: None,
+			restart_interval: None,
 		}
 	}
 
-	/// Encodes the image ```image```
+	/// Create a new encoder that writes its output to ```w``` using
+	/// quantization tables scaled to ```quality``` (1-100).
+	pub fn new_with_quality(w: W, quality: u8) -> JPEGEncoder<W> {
+		let mut e = JPEGEncoder::new(w);
+		e.set_quality(quality);
+
+		e
+	}
+
+	/// Scale the quantization tables to the given ```quality``` (1-100) using
+	/// the classic IJG mapping. Lower quality yields larger divisors and smaller
+	/// files; the scaled tables drive both the DQT segment and the coefficient
+	/// quantization so the two stay consistent.
+	pub fn set_quality(&mut self, quality: u8) {
+		let quality = if quality < 1 {1} else if quality > 100 {100} else {quality};
+
+		let scale = if quality < 50 {
+			5000u32 / quality as u32
+		} else {
+			200u32 - quality as u32 * 2
+		};
+
+		let mut tables = Vec::new();
+		for &b in STD_LUMA_QTABLE.iter() {
+			tables.push(scale_qvalue(b, scale));
+		}
+		for &b in STD_CHROMA_QTABLE.iter() {
+			tables.push(scale_qvalue(b, scale));
+		}
+
+		self.tables = tables;
+	}
+
+	/// Select the chroma subsampling ratio for color encoding. This sets the
+	/// luma component's sampling factors, which flow into the frame header and
+	/// drive the per-MCU block ordering; chroma stays at 1x1.
+	pub fn set_sampling(&mut self, sampling: SamplingFactor) {
+		let (h, v) = match sampling {
+			Sampling444 => (1u8, 1u8),
+			Sampling422 => (2u8, 1u8),
+			Sampling420 => (2u8, 2u8),
+		};
+
+		self.components.get_mut(0).h = h;
+		self.components.get_mut(0).v = v;
+	}
+
+	/// Enable or disable optimized Huffman coding. When enabled the encoder makes
+	/// a first pass over the image to count symbol statistics and derives per-image
+	/// Huffman tables from them, which is smaller but slower than the default
+	/// single-pass mode that ships the fixed Annex-K tables. CMYK and YCCK encodes
+	/// always ship the fixed tables.
+	pub fn set_optimize(&mut self, optimize: bool) {
+		self.optimize = optimize;
+	}
+
+	/// Set the restart interval to ```n``` MCUs, or clear it when ```n == 0```. With
+	/// an interval the scan is broken into independently decodable runs separated by
+	/// ```RSTm``` markers, which bounds the damage a corrupt byte can do and lets a
+	/// decoder pick the stream up in parallel. CMYK and YCCK encodes are written
+	/// without restart markers.
+	pub fn set_restart_interval(&mut self, n: u16) {
+		self.restart_interval = if n == 0 {None} else {Some(n)};
+	}
+
+	//Pad to a byte boundary and write the next restart marker, cycling RST0..RST7.
+	fn write_restart(&mut self, m: u8) -> IoResult<()> {
+		let _ = try!(self.pad_byte());
+
+		//Drop the padding bits so the marker starts on a clean boundary.
+		self.accumulator = 0;
+		self.nbits       = 0;
+
+		let _ = try!(self.w.write_u8(0xFF));
+		self.w.write_u8(RST0 + m)
+	}
+
+	/// Attach a raw ICC color profile to be embedded in the output. The profile is
+	/// written verbatim as one or more APP2 segments right after the APP0 header;
+	/// without one the image carries only the JFIF header and is assumed to be sRGB.
+	pub fn set_icc_profile(&mut self, profile: &[u8]) {
+		self.icc_profile = Some(profile.to_vec());
+	}
+
+	//Emit the stored ICC profile, if any, as a run of APP2 segments.
+	fn write_icc_profile(&mut self) -> IoResult<()> {
+		let profile = match self.icc_profile {
+			Some(ref p) => p.clone(),
+			None        => return Ok(()),
+		};
+
+		let count = (profile.len() + ICC_MAX_CHUNK - 1) / ICC_MAX_CHUNK;
+
+		for (i, chunk) in profile.as_slice().chunks(ICC_MAX_CHUNK).enumerate() {
+			let mut m = MemWriter::new();
+
+			let _ = m.write(ICC_MARKER);
+			let _ = m.write_u8((i + 1) as u8);
+			let _ = m.write_u8(count as u8);
+			let _ = m.write(chunk);
+
+			let _ = try!(self.write_segment(APP2, Some(m.unwrap())));
+		}
+
+		Ok(())
+	}
+
+	/// Encodes the packed 8-bit image ```image```
 	/// that has dimensions ```width``` and ```height```
 	/// and ```ColorType``` ```c```
-	/// The Image in encoded with subsampling ratio 4:2:2
+	/// Color images use 4:4:4 sampling unless ```set_sampling``` selects another ratio.
+	/// CMYK and YCCK inputs are written as four-component JPEGs tagged with an Adobe
+	/// APP14 segment so decoders can recover the inversion convention.
 	pub fn encode(&mut self,
 		      image: &[u8],
 		      width: u32,
@@ -183,14 +415,35 @@ impl<W: Writer> JPEGEncoder<W> {
 		      c: colortype::ColorType) -> IoResult<()> {
 
 		let n = colortype::num_components(c);
-		let num_components = if n == 1 || n == 2 {1}
-							 else {3};
+		let source = ByteSource {
+			buf: image,
+			width: width as uint,
+			height: height as uint,
+			bpp: n,
+			grey: n == 1 || n == 2,
+		};
+
+		match c {
+			colortype::CMYK(8) => self.encode_cmyk(&source, false),
+			colortype::YCCK(8) => self.encode_cmyk(&source, true),
+			_                  => self.encode_image(&source),
+		}
+	}
+
+	/// Encodes the image pulled from ```source```, fetching ```8x8``` blocks on demand.
+	/// This allows encoding directly from any pixel layout without first building a
+	/// packed RGB buffer.
+	pub fn encode_image<S: PixelSource>(&mut self, source: &S) -> IoResult<()> {
+		let (width, height) = source.dimensions();
+		let num_components = source.components();
 
 		let _ = try!(self.write_segment(SOI, None));
 
 		let buf = build_jfif_header();
 		let _   = try!(self.write_segment(APP0, Some(buf)));
 
+		let _ = try!(self.write_icc_profile());
+
 		let buf = build_frame_header(8, width as u16, height as u16, self.components.slice_to(num_components));
 		let _   = try!(self.write_segment(SOF0, Some(buf)));
 
@@ -204,38 +457,72 @@ impl<W: Writer> JPEGEncoder<W> {
 			let _   = try!(self.write_segment(DQT, Some(buf)));
 		}
 
-		let numcodes = STD_LUMA_DC_CODE_LENGTHS;
-		let values   = STD_LUMA_DC_VALUES;
-		let buf = build_huffman_segment(DCCLASS, LUMADESTINATION, numcodes, values);
-		let _   = try!(self.write_segment(DHT, Some(buf)));
+		//In optimize mode a first pass gathers symbol statistics and derives the
+		//per-image Huffman tables, which also rebuilds the encoder's code luts so
+		//the writing pass below uses them. Otherwise the standard tables are shipped.
+		let opt = if self.optimize {
+			let mut freqs = Freqs::new();
 
-		let numcodes = STD_LUMA_AC_CODE_LENGTHS;
-		let values   = STD_LUMA_AC_VALUES;
-		let buf = build_huffman_segment(ACCLASS, LUMADESTINATION, numcodes, values);
-		let _   = try!(self.write_segment(DHT, Some(buf)));
+			if num_components == 1 {
+				try!(self.encode_grey(source, width, height, Some(&mut freqs)));
+			} else {
+				try!(self.encode_rgb(source, width, height, Some(&mut freqs)));
+			}
 
-		if num_components == 3 {
-			let numcodes = STD_CHROMA_DC_CODE_LENGTHS;
-			let values   = STD_CHROMA_DC_VALUES;
-			let buf = build_huffman_segment(DCCLASS, CHROMADESTINATION, numcodes, values);
-			let _   = try!(self.write_segment(DHT, Some(buf)));
+			Some(self.build_optimized_tables(&freqs, num_components))
+		} else {
+			None
+		};
+
+		match opt {
+			Some(ref tables) => {
+				for &(class, dest, ref numcodes, ref values) in tables.iter() {
+					let buf = build_huffman_segment(class, dest, numcodes.as_slice(), values.as_slice());
+					let _   = try!(self.write_segment(DHT, Some(buf)));
+				}
+			}
 
-			let numcodes = STD_CHROMA_AC_CODE_LENGTHS;
-			let values   = STD_CHROMA_AC_VALUES;
-			let buf = build_huffman_segment(ACCLASS, CHROMADESTINATION, numcodes, values);
-			let _   = try!(self.write_segment(DHT, Some(buf)));
+			None => {
+				let numcodes = STD_LUMA_DC_CODE_LENGTHS;
+				let values   = STD_LUMA_DC_VALUES;
+				let buf = build_huffman_segment(DCCLASS, LUMADESTINATION, numcodes, values);
+				let _   = try!(self.write_segment(DHT, Some(buf)));
+
+				let numcodes = STD_LUMA_AC_CODE_LENGTHS;
+				let values   = STD_LUMA_AC_VALUES;
+				let buf = build_huffman_segment(ACCLASS, LUMADESTINATION, numcodes, values);
+				let _   = try!(self.write_segment(DHT, Some(buf)));
+
+				if num_components == 3 {
+					let numcodes = STD_CHROMA_DC_CODE_LENGTHS;
+					let values   = STD_CHROMA_DC_VALUES;
+					let buf = build_huffman_segment(DCCLASS, CHROMADESTINATION, numcodes, values);
+					let _   = try!(self.write_segment(DHT, Some(buf)));
+
+					let numcodes = STD_CHROMA_AC_CODE_LENGTHS;
+					let values   = STD_CHROMA_AC_VALUES;
+					let buf = build_huffman_segment(ACCLASS, CHROMADESTINATION, numcodes, values);
+					let _   = try!(self.write_segment(DHT, Some(buf)));
+				}
+			}
+		}
+
+		match self.restart_interval {
+			Some(interval) => {
+				let buf = vec![(interval >> 8) as u8, interval as u8];
+				let _   = try!(self.write_segment(DRI, Some(buf)));
+			}
+			None => {}
 		}
 
 		let buf = build_scan_header(self.components.slice_to(num_components));
 		let _   = try!(self.write_segment(SOS, Some(buf)));
 
-		match c {
-			colortype::RGB(8)   => try!(self.encode_rgb(image, width as uint, height as uint, 3)),
-			colortype::RGBA(8)  => try!(self.encode_rgb(image, width as uint, height as uint, 4)),
-			colortype::Grey(8)  => try!(self.encode_grey(image, width as uint, height as uint, 1)),
-			colortype::GreyA(8) => try!(self.encode_grey(image, width as uint, height as uint, 2)),
-			_  => fail!("unimplemented!")
-		};
+		if num_components == 1 {
+			try!(self.encode_grey(source, width, height, None));
+		} else {
+			try!(self.encode_rgb(source, width, height, None));
+		}
 
 		let _ = try!(self.pad_byte());
 		self.write_segment(EOI, None)
@@ -339,15 +626,54 @@ impl<W: Writer> JPEGEncoder<W> {
 		Ok(dcval)
 	}
 
-	fn encode_grey(&mut self, image: &[u8], width: uint, height: uint, bpp: uint) -> IoResult<()> {
+	/// Derive the per-image Huffman tables from the counted statistics, rebuild
+	/// the encoder's code luts so the writing pass uses them, and return the
+	/// ```(class, destination, BITS, HUFFVAL)``` tuples to emit as DHT segments.
+	fn build_optimized_tables(&mut self, freqs: &Freqs, num_components: uint) -> Vec<(u8, u8, Vec<u8>, Vec<u8>)> {
+		let mut tables = Vec::new();
+
+		let (ldc_bits, ldc_val) = build_optimal_table(freqs.luma_dc.as_slice());
+		let (lac_bits, lac_val) = build_optimal_table(freqs.luma_ac.as_slice());
+
+		self.luma_dctable = build_huff_lut(ldc_bits.as_slice(), ldc_val.as_slice());
+		self.luma_actable = build_huff_lut(lac_bits.as_slice(), lac_val.as_slice());
+
+		tables.push((DCCLASS, LUMADESTINATION, ldc_bits, ldc_val));
+		tables.push((ACCLASS, LUMADESTINATION, lac_bits, lac_val));
+
+		if num_components == 3 {
+			let (cdc_bits, cdc_val) = build_optimal_table(freqs.chroma_dc.as_slice());
+			let (cac_bits, cac_val) = build_optimal_table(freqs.chroma_ac.as_slice());
+
+			self.chroma_dctable = build_huff_lut(cdc_bits.as_slice(), cdc_val.as_slice());
+			self.chroma_actable = build_huff_lut(cac_bits.as_slice(), cac_val.as_slice());
+
+			tables.push((DCCLASS, CHROMADESTINATION, cdc_bits, cdc_val));
+			tables.push((ACCLASS, CHROMADESTINATION, cac_bits, cac_val));
+		}
+
+		tables
+	}
+
+	fn encode_grey<S: PixelSource>(&mut self, source: &S, width: uint, height: uint,
+				       mut count: Option<&mut Freqs>) -> IoResult<()> {
 		let mut yblock     = [0u8, ..64];
 		let mut y_dcprev   = 0;
 		let mut dct_yblock = [0i32, ..64];
 
+		//The counting pass must reset the DC predictors at the same boundaries as
+		//the encoding pass so its statistics match the emitted symbols; only the
+		//marker itself is suppressed while counting.
+		let interval = self.restart_interval;
+		let writing  = count.is_none();
+		let total    = ((width + 7) / 8) * ((height + 7) / 8);
+		let mut mcu  = 0u;
+		let mut rst  = 0u8;
+
 		for y in range_step(0, height, 8) {
 			for x in range_step(0, width, 8) {
 				//RGB -> YCbCr
-				copy_blocks_grey(image, x, y, width, bpp, &mut yblock);
+				copy_blocks_grey(source, x, y, &mut yblock);
 
 				//Level shift and fdct
 				//Coeffs are scaled by 8
@@ -358,22 +684,42 @@ impl<W: Writer> JPEGEncoder<W> {
 					dct_yblock[i]   = ((dct_yblock[i] / 8)   as f32 / self.tables.slice_to(64)[i] as f32).round() as i32;
 				}
 
-				let la = self.luma_actable.clone();
-				let ld = self.luma_dctable.clone();
+				match count {
+					Some(ref mut f) => {
+						y_dcprev = count_block(dct_yblock, y_dcprev, &mut f.luma_dc, &mut f.luma_ac);
+					}
+					None => {
+						let la = self.luma_actable.clone();
+						let ld = self.luma_dctable.clone();
+
+						y_dcprev  = try!(self.write_block(dct_yblock, y_dcprev, ld.as_slice(), la.as_slice()));
+					}
+				}
 
-				y_dcprev  = try!(self.write_block(dct_yblock, y_dcprev, ld.as_slice(), la.as_slice()));
+				mcu += 1;
+				match interval {
+					Some(n) if mcu % n as uint == 0 && mcu < total => {
+						if writing {
+							let _ = try!(self.write_restart(rst));
+							rst = (rst + 1) % 8;
+						}
+						y_dcprev = 0;
+					}
+					_ => {}
+				}
 			}
 		}
 
 		Ok(())
 	}
 
-	fn encode_rgb(&mut self, image: &[u8], width: uint, height: uint, bpp: uint) -> IoResult<()> {
+	fn encode_rgb<S: PixelSource>(&mut self, source: &S, width: uint, height: uint,
+				     mut count: Option<&mut Freqs>) -> IoResult<()> {
 		let mut y_dcprev = 0;
 		let mut cb_dcprev = 0;
 		let mut cr_dcprev = 0;
 
-		let mut dct_yblock   = [0i32, ..64];
+		let mut dct_block    = [0i32, ..64];
 		let mut dct_cb_block = [0i32, ..64];
 		let mut dct_cr_block = [0i32, ..64];
 
@@ -381,37 +727,179 @@ impl<W: Writer> JPEGEncoder<W> {
 		let mut cb_block = [0u8, ..64];
 		let mut cr_block = [0u8, ..64];
 
-		for y in range_step(0, height, 8) {
-			for x in range_step(0, width, 8) {
-				//RGB -> YCbCr
-				copy_blocks_ycbcr(image, x, y, width, bpp, &mut yblock, &mut cb_block, &mut cr_block);
+		//The luma sampling factors size the MCU; chroma is always 1x1 here.
+		let hmax = self.components.as_slice()[0].h as uint;
+		let vmax = self.components.as_slice()[0].v as uint;
+
+		let mcu_w = hmax * 8;
+		let mcu_h = vmax * 8;
+
+		//The counting pass must reset the DC predictors at the same boundaries as
+		//the encoding pass so its statistics match the emitted symbols; only the
+		//marker itself is suppressed while counting.
+		let interval = self.restart_interval;
+		let writing  = count.is_none();
+		let total    = ((width + mcu_w - 1) / mcu_w) * ((height + mcu_h - 1) / mcu_h);
+		let mut mcu  = 0u;
+		let mut rst  = 0u8;
+
+		for my in range_step(0, height, mcu_h) {
+			for mx in range_step(0, width, mcu_w) {
+				//The luma blocks of this MCU, in raster order.
+				for by in range(0, vmax) {
+					for bx in range(0, hmax) {
+						copy_luma_block(source, mx + bx * 8, my + by * 8, &mut yblock);
+
+						transform::fdct(yblock.as_slice(), dct_block);
+						for i in range(0u, 64) {
+							dct_block[i] = ((dct_block[i] / 8) as f32 / self.tables.slice_to(64)[i] as f32).round() as i32;
+						}
+
+						match count {
+							Some(ref mut f) => {
+								y_dcprev = count_block(dct_block, y_dcprev, &mut f.luma_dc, &mut f.luma_ac);
+							}
+							None => {
+								let la = self.luma_actable.clone();
+								let ld = self.luma_dctable.clone();
+
+								y_dcprev = try!(self.write_block(dct_block, y_dcprev, ld.as_slice(), la.as_slice()));
+							}
+						}
+					}
+				}
+
+				//A single downsampled Cb and Cr block per MCU.
+				downsample_chroma(source, mx, my, hmax, vmax, &mut cb_block, &mut cr_block);
 
-				//Level shift and fdct
-				//Coeffs are scaled by 8
-				transform::fdct(yblock.as_slice(), dct_yblock);
 				transform::fdct(cb_block.as_slice(), dct_cb_block);
 				transform::fdct(cr_block.as_slice(), dct_cr_block);
 
-				//Quantization
 				for i in range(0u, 64) {
-					dct_yblock[i]   = ((dct_yblock[i] / 8)   as f32 / self.tables.slice_to(64)[i] as f32).round() as i32;
 					dct_cb_block[i] = ((dct_cb_block[i] / 8) as f32 / self.tables.slice_from(64)[i] as f32).round() as i32;
 					dct_cr_block[i] = ((dct_cr_block[i] / 8) as f32 / self.tables.slice_from(64)[i] as f32).round() as i32;
 				}
 
-				let la = self.luma_actable.clone();
-				let ld = self.luma_dctable.clone();
-				let cd = self.chroma_dctable.clone();
-				let ca = self.chroma_actable.clone();
+				match count {
+					Some(ref mut f) => {
+						cb_dcprev = count_block(dct_cb_block, cb_dcprev, &mut f.chroma_dc, &mut f.chroma_ac);
+						cr_dcprev = count_block(dct_cr_block, cr_dcprev, &mut f.chroma_dc, &mut f.chroma_ac);
+					}
+					None => {
+						let cd = self.chroma_dctable.clone();
+						let ca = self.chroma_actable.clone();
+
+						cb_dcprev = try!(self.write_block(dct_cb_block, cb_dcprev, cd.as_slice(), ca.as_slice()));
+						cr_dcprev = try!(self.write_block(dct_cr_block, cr_dcprev, cd.as_slice(), ca.as_slice()));
+					}
+				}
 
-				y_dcprev  = try!(self.write_block(dct_yblock, y_dcprev, ld.as_slice(), la.as_slice()));
-				cb_dcprev = try!(self.write_block(dct_cb_block, cb_dcprev, cd.as_slice(), ca.as_slice()));
-				cr_dcprev = try!(self.write_block(dct_cr_block, cr_dcprev, cd.as_slice(), ca.as_slice()));
+				mcu += 1;
+				match interval {
+					Some(n) if mcu % n as uint == 0 && mcu < total => {
+						if writing {
+							let _ = try!(self.write_restart(rst));
+							rst = (rst + 1) % 8;
+						}
+						y_dcprev  = 0;
+						cb_dcprev = 0;
+						cr_dcprev = 0;
+					}
+					_ => {}
+				}
 			}
 		}
 
 		Ok(())
 	}
+
+	/// Encodes a four-component CMYK (```ycck == false```) or YCCK (```ycck == true```)
+	/// image. The luma tables serve the C/Y and K channels, the chroma tables the
+	/// two middle channels; an Adobe APP14 segment records which convention applies.
+	fn encode_cmyk<S: PixelSource>(&mut self, source: &S, ycck: bool) -> IoResult<()> {
+		let (width, height) = source.dimensions();
+
+		//C/Y and K ride the luma tables, M/Cb and Y/Cr the chroma tables.
+		let components = [
+			Component {id: LUMAID,       h: 1, v: 1, tq: LUMADESTINATION,   dc_table: LUMADESTINATION,   ac_table: LUMADESTINATION,   dc_pred: 0},
+			Component {id: CHROMABLUEID, h: 1, v: 1, tq: CHROMADESTINATION, dc_table: CHROMADESTINATION, ac_table: CHROMADESTINATION, dc_pred: 0},
+			Component {id: CHROMAREDID,  h: 1, v: 1, tq: CHROMADESTINATION, dc_table: CHROMADESTINATION, ac_table: CHROMADESTINATION, dc_pred: 0},
+			Component {id: KEYID,        h: 1, v: 1, tq: LUMADESTINATION,   dc_table: LUMADESTINATION,   ac_table: LUMADESTINATION,   dc_pred: 0}
+		];
+
+		let _ = try!(self.write_segment(SOI, None));
+
+		let buf = build_jfif_header();
+		let _   = try!(self.write_segment(APP0, Some(buf)));
+
+		let _ = try!(self.write_icc_profile());
+
+		let transform = if ycck {ADOBE_TRANSFORM_YCCK} else {ADOBE_TRANSFORM_CMYK};
+		let buf = build_adobe_header(transform);
+		let _   = try!(self.write_segment(APP14, Some(buf)));
+
+		let buf = build_frame_header(8, width as u16, height as u16, components.as_slice());
+		let _   = try!(self.write_segment(SOF0, Some(buf)));
+
+		assert!(self.tables.len() / 64 == 2);
+		let t = self.tables.clone();
+		for (i, table) in t.as_slice().chunks(64).enumerate().take(2) {
+			let buf = build_quantization_segment(8, i as u8, table);
+			let _   = try!(self.write_segment(DQT, Some(buf)));
+		}
+
+		let buf = build_huffman_segment(DCCLASS, LUMADESTINATION, STD_LUMA_DC_CODE_LENGTHS, STD_LUMA_DC_VALUES);
+		let _   = try!(self.write_segment(DHT, Some(buf)));
+		let buf = build_huffman_segment(ACCLASS, LUMADESTINATION, STD_LUMA_AC_CODE_LENGTHS, STD_LUMA_AC_VALUES);
+		let _   = try!(self.write_segment(DHT, Some(buf)));
+		let buf = build_huffman_segment(DCCLASS, CHROMADESTINATION, STD_CHROMA_DC_CODE_LENGTHS, STD_CHROMA_DC_VALUES);
+		let _   = try!(self.write_segment(DHT, Some(buf)));
+		let buf = build_huffman_segment(ACCLASS, CHROMADESTINATION, STD_CHROMA_AC_CODE_LENGTHS, STD_CHROMA_AC_VALUES);
+		let _   = try!(self.write_segment(DHT, Some(buf)));
+
+		let buf = build_scan_header(components.as_slice());
+		let _   = try!(self.write_segment(SOS, Some(buf)));
+
+		let mut dcprev = [0i32, ..4];
+
+		let mut block    = [0u8, ..64];
+		let mut dct      = [0i32, ..64];
+
+		let mut blocks = [[0u8, ..64], ..4];
+
+		for y in range_step(0, height, 8) {
+			for x in range_step(0, width, 8) {
+				copy_blocks_cmyk(source, x, y, ycck, &mut blocks);
+
+				for c in range(0u, 4) {
+					for i in range(0u, 64) {
+						block[i] = blocks[c][i];
+					}
+
+					transform::fdct(block.as_slice(), dct);
+
+					//The K and first channel quantize against the luma table.
+					let qt = if c == 1 || c == 2 {self.tables.slice_from(64)}
+						 else {self.tables.slice_to(64)};
+
+					for i in range(0u, 64) {
+						dct[i] = ((dct[i] / 8) as f32 / qt[i] as f32).round() as i32;
+					}
+
+					let (dct_table, ac_table) = if c == 1 || c == 2 {
+						(self.chroma_dctable.clone(), self.chroma_actable.clone())
+					} else {
+						(self.luma_dctable.clone(), self.luma_actable.clone())
+					};
+
+					dcprev[c] = try!(self.write_block(dct, dcprev[c], dct_table.as_slice(), ac_table.as_slice()));
+				}
+			}
+		}
+
+		let _ = try!(self.pad_byte());
+		self.write_segment(EOI, None)
+	}
 }
 
 fn build_jfif_header() -> Vec<u8> {
@@ -430,6 +918,20 @@ fn build_jfif_header() -> Vec<u8> {
 	m.unwrap()
 }
 
+//The Adobe APP14 payload: the "Adobe" tag, a version and two flag words, and the
+//colour transform byte (0 for CMYK, 1 for YCbCr, 2 for YCCK).
+fn build_adobe_header(transform: u8) -> Vec<u8> {
+	let mut m = MemWriter::new();
+
+	let _ = m.write_str("Adobe");
+	let _ = m.write_be_u16(100);
+	let _ = m.write_be_u16(0);
+	let _ = m.write_be_u16(0);
+	let _ = m.write_u8(transform);
+
+	m.unwrap()
+}
+
 fn build_frame_header(precision: u8,
 		      width: u16,
 		      height: u16,
@@ -516,6 +1018,164 @@ fn build_quantization_segment(precision: u8,
 	m.unwrap()
 }
 
+//The counting-mode twin of ```write_block```: walks the same symbol sequence
+//but tallies each DC size and ```(run<<4)|size``` AC symbol (plus the ZRL and
+//EOB symbols) into the frequency arrays instead of emitting any bits.
+fn count_block(block: &[i32], prevdc: i32, dc_freq: &mut Vec<u32>, ac_freq: &mut Vec<u32>) -> i32 {
+	let dcval = block[0];
+	let diff  = dcval - prevdc;
+	let (size, _) = encode_coefficient(diff);
+
+	dc_freq.as_mut_slice()[size as uint] += 1;
+
+	let mut zero_run = 0;
+	let mut k = 0u;
+
+	loop {
+		k += 1;
+
+		if block[UNZIGZAG[k] as uint] == 0 {
+			if k == 63 {
+				ac_freq.as_mut_slice()[0x00] += 1;
+				break
+			}
+
+			zero_run += 1;
+		}
+		else {
+			while zero_run > 15 {
+				ac_freq.as_mut_slice()[0xF0] += 1;
+				zero_run -= 16;
+			}
+
+			let (size, _) = encode_coefficient(block[UNZIGZAG[k] as uint]);
+			let symbol = (zero_run << 4) | size;
+
+			ac_freq.as_mut_slice()[symbol as uint] += 1;
+
+			zero_run = 0;
+
+			if k == 63 {
+				break
+			}
+		}
+	}
+
+	dcval
+}
+
+//Build a ```(BITS, HUFFVAL)``` pair from symbol frequencies using the JPEG
+//Annex K.2 procedure, then length-limit the result to 16 bits. ```freq``` is a
+//257-entry array; ```freq[256]``` is reserved for the all-ones code so no real
+//symbol ever receives it.
+fn build_optimal_table(freq: &[u32]) -> (Vec<u8>, Vec<u8>) {
+	let mut freq = {
+		let mut f = [0u32, ..257];
+		for i in range(0u, 257) {
+			f[i] = freq[i];
+		}
+		f[256] = 1;
+		f
+	};
+
+	let mut codesize = [0i32, ..257];
+	let mut others   = [-1i32, ..257];
+
+	//Repeatedly merge the two least-frequent still-active symbols, lengthening
+	//the code of every symbol in the merged set.
+	loop {
+		let mut c1 = -1i32;
+		let mut v  = 1000000000u32;
+		for i in range(0u, 257) {
+			if freq[i] != 0 && freq[i] <= v {
+				v  = freq[i];
+				c1 = i as i32;
+			}
+		}
+
+		let mut c2 = -1i32;
+		let mut v  = 1000000000u32;
+		for i in range(0u, 257) {
+			if freq[i] != 0 && freq[i] <= v && i as i32 != c1 {
+				v  = freq[i];
+				c2 = i as i32;
+			}
+		}
+
+		if c2 < 0 {
+			break
+		}
+
+		freq[c1 as uint] += freq[c2 as uint];
+		freq[c2 as uint]  = 0;
+
+		codesize[c1 as uint] += 1;
+		let mut c = c1;
+		while others[c as uint] >= 0 {
+			c = others[c as uint];
+			codesize[c as uint] += 1;
+		}
+		others[c as uint] = c2;
+
+		codesize[c2 as uint] += 1;
+		let mut c = c2;
+		while others[c as uint] >= 0 {
+			c = others[c as uint];
+			codesize[c as uint] += 1;
+		}
+	}
+
+	//Tally how many codes have each length.
+	let mut bits = [0i32, ..33];
+	for i in range(0u, 257) {
+		if codesize[i] > 0 {
+			bits[codesize[i] as uint] += 1;
+		}
+	}
+
+	//Promote any code longer than 16 bits down into shorter slots.
+	let mut i = 32u;
+	while i > 16 {
+		while bits[i] > 0 {
+			let mut j = i - 2;
+			while bits[j] == 0 {
+				j -= 1;
+			}
+
+			bits[i]     -= 2;
+			bits[i - 1] += 1;
+			bits[j + 1] += 2;
+			bits[j]     -= 1;
+		}
+
+		i -= 1;
+	}
+
+	//Drop the reserved code from the longest non-empty length.
+	let mut i = 16u;
+	while bits[i] == 0 {
+		i -= 1;
+	}
+	bits[i] -= 1;
+
+	//Emit BITS[1..16] and HUFFVAL ordered by code length then symbol value.
+	let mut numcodes = Vec::from_elem(16, 0u8);
+	for l in range(1u, 17) {
+		numcodes.as_mut_slice()[l - 1] = bits[l] as u8;
+	}
+
+	let mut huffval = Vec::new();
+	for size in range(1u, 33) {
+		for value in range(0u, 256) {
+			if codesize[value] == size as i32 {
+				huffval.push(value as u8);
+			}
+		}
+	}
+
+	(numcodes, huffval)
+}
+
 fn encode_coefficient(coefficient: i32) -> (u8, u16) {
 	let mut magnitude = coefficient.abs() as u16;
 	let mut num_bits  = 0u8;
@@ -532,6 +1192,14 @@ fn encode_coefficient(coefficient: i32) -> (u8, u16) {
 	(num_bits, val)
 }
 
+fn scale_qvalue(base: u8, scale: u32) -> u8 {
+	let v = (base as u32 * scale + 50) / 100;
+
+	if v < 1 {1}
+	else if v > 255 {255}
+	else {v as u8}
+}
+
 fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
 	let r = r as f32;
 	let g = g as f32;
@@ -544,53 +1212,96 @@ fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
 	(y as u8, cb as u8, cr as u8)
 }
 
-fn value_at(s: &[u8], index: uint) -> u8 {
-	if index < s.len() {
-		s[index]
-	} else {
-		s[s.len() - 1]
+fn copy_luma_block<S: PixelSource>(source: &S,
+		     x0: uint,
+		     y0: uint,
+		     yb: &mut [u8, ..64]) {
+
+	for y in range(0u, 8) {
+		for x in range(0u, 8) {
+			let (r, g, b) = source.pixel(x0 + x, y0 + y);
+			let (yc, _, _) = rgb_to_ycbcr(r, g, b);
+
+			yb[y * 8 + x] = yc;
+		}
 	}
 }
 
-fn copy_blocks_ycbcr(source: &[u8],
-		     x0: uint,
-		     y0: uint,
-		     width: uint,
-		     bpp: uint,
-		     yb: &mut [u8, ..64],
+//Build the Cb/Cr blocks for one MCU, averaging each chroma sample over the
+//```hmax x vmax``` luma region it covers.
+fn downsample_chroma<S: PixelSource>(source: &S,
+		     mcu_x: uint,
+		     mcu_y: uint,
+		     hmax: uint,
+		     vmax: uint,
 		     cbb: &mut [u8, ..64],
 		     crb: &mut [u8, ..64]) {
 
-	for y in range(0u, 8) {
-		let ystride = (y0 + y) * bpp * width;
-		for x in range(0u, 8) {
-			let xstride = x0 * bpp + x * bpp;
+	let n = (hmax * vmax) as u32;
 
-			let r = value_at(source, ystride + xstride + 0);
-			let g = value_at(source, ystride + xstride + 1);
-			let b = value_at(source, ystride + xstride + 2);
+	for cy in range(0u, 8) {
+		for cx in range(0u, 8) {
+			let mut sum_cb = 0u32;
+			let mut sum_cr = 0u32;
 
-			let (yc, cb, cr) = rgb_to_ycbcr(r, g, b);
+			for dy in range(0, vmax) {
+				for dx in range(0, hmax) {
+					let px = mcu_x + cx * hmax + dx;
+					let py = mcu_y + cy * vmax + dy;
+
+					let (r, g, b) = source.pixel(px, py);
+					let (_, cb, cr) = rgb_to_ycbcr(r, g, b);
+
+					sum_cb += cb as u32;
+					sum_cr += cr as u32;
+				}
+			}
 
-			yb[y * 8 + x]  = yc;
-			cbb[y * 8 + x] = cb;
-			crb[y * 8 + x] = cr;
+			cbb[cy * 8 + cx] = (sum_cb / n) as u8;
+			crb[cy * 8 + cx] = (sum_cr / n) as u8;
 		}
 	}
 }
 
-fn copy_blocks_grey(source: &[u8],
+fn copy_blocks_grey<S: PixelSource>(source: &S,
 		    x0: uint,
 		    y0: uint,
-		    width: uint,
-		    bpp: uint,
 		    gb: &mut [u8, ..64]) {
 
 	for y in range(0u, 8) {
-		let ystride = (y0 + y) * bpp * width;
 		for x in range(0u, 8) {
-			let xstride = x0 * bpp + x * bpp;
-			gb[y * 8 + x] = value_at(source, ystride + xstride + 1);
+			let (r, g, b) = source.pixel(x0 + x, y0 + y);
+			let (yc, _, _) = rgb_to_ycbcr(r, g, b);
+			gb[y * 8 + x] = yc;
+		}
+	}
+}
+
+//Deinterleave one 8x8 block from each of the four channels. For CMYK the channels
+//pass through untouched; for YCCK the CMY triple is inverted back to RGB and run
+//through ```rgb_to_ycbcr```, while K is copied through either way.
+fn copy_blocks_cmyk<S: PixelSource>(source: &S,
+			    x0: uint,
+			    y0: uint,
+			    ycck: bool,
+			    blocks: &mut [[u8, ..64], ..4]) {
+
+	for y in range(0u, 8) {
+		for x in range(0u, 8) {
+			let (c0, c1, c2) = source.pixel(x0 + x, y0 + y);
+			let k = source.key(x0 + x, y0 + y);
+
+			let (v0, v1, v2) = if ycck {
+				rgb_to_ycbcr(255 - c0, 255 - c1, 255 - c2)
+			} else {
+				(c0, c1, c2)
+			};
+
+			let i = y * 8 + x;
+			blocks[0][i] = v0;
+			blocks[1][i] = v1;
+			blocks[2][i] = v2;
+			blocks[3][i] = k;
 		}
 	}
 }
@@ -61,6 +61,43 @@ pub fn unfilter(filter: FilterType, bpp: uint, previous: &[u8], current: &mut [u
 	}
 }
 
+/// Filter ```current``` with whichever of the five filter types yields the
+/// smallest sum-of-absolute-differences, and return that type alongside the
+/// filtered bytes. The heuristic treats each filtered byte as a signed value and
+/// sums ```min(b, 256 - b)``` over the row, which is the usual proxy PNG writers
+/// use to favour rows that compress well. The caller prepends the filter-type
+/// byte when writing the scanline.
+pub fn filter_adaptive(bpp: uint, previous: &[u8], current: &[u8]) -> (FilterType, Vec<u8>) {
+	let candidates = [NoFilter, Sub, Up, Avg, Paeth];
+
+	let mut best_type  = NoFilter;
+	let mut best_bytes = Vec::new();
+	let mut best_score = None;
+
+	for &method in candidates.iter() {
+		let mut row = Vec::from_fn(current.len(), |i| current[i]);
+		filter(method, bpp, previous, row.as_mut_slice());
+
+		let mut score = 0u;
+		for &b in row.iter() {
+			score += ::std::cmp::min(b as uint, 256 - b as uint);
+		}
+
+		let better = match best_score {
+			Some(s) => score < s,
+			None    => true,
+		};
+
+		if better {
+			best_score = Some(score);
+			best_type  = method;
+			best_bytes = row;
+		}
+	}
+
+	(best_type, best_bytes)
+}
+
 pub fn filter(method: FilterType, bpp: uint, previous: &[u8], current: &mut [u8]) {
 	let len  = current.len();
 	let orig = Vec::from_fn(len, |i| current[i]);
@@ -49,16 +49,37 @@ impl TableElement {
 
 enum BlockType {Stored, Compressed}
 
+//A malformed stream should degrade to an error rather than a panic, so callers
+//decoding untrusted files can recover. These mirror the cases minipng reports.
+fn bad_data<T>(detail: &str) -> IoResult<T> {
+	Err(io::IoError {
+		kind:   io::InvalidInput,
+		desc:   "invalid deflate stream",
+		detail: Some(detail.to_string()),
+	})
+}
+
 pub struct Inflater<R> {
 	h: HuffReader<R>,
 
-	buf: ~[u8],
-	pos: u64,
+	//The last WINDOW_SIZE output bytes, kept in a ring so back-references resolve
+	//in O(32 KiB) no matter how large the stream is.
+	window: ~[u8],
+	wpos: uint,
+	written: u64,
+
+	//Decoded bytes not yet handed to the caller's `read`; drained each call so
+	//steady-state memory stays bounded.
+	out: ~[u8],
+	out_pos: uint,
 
 	final: bool,
+	reading_block: bool,
 	btype: BlockType,
 	block_length: u32,
-	
+
+	limit: Option<uint>,
+
 	ctable: ~[TableElement],
 	lltable: ~[TableElement],
 	dtable: ~[TableElement],
@@ -68,22 +89,51 @@ impl<R: Reader> Inflater<R> {
 	pub fn new(r: R) -> Inflater<R> {
 		Inflater {
 			h: HuffReader::new(r),
-			
-			buf: ~[],
-			pos: 0,
-			
+
+			window: slice::from_elem(WINDOW_SIZE, 0u8),
+			wpos: 0,
+			written: 0,
+
+			out: ~[],
+			out_pos: 0,
+
 			final: false,
+			reading_block: false,
 			block_length: 0,
 			btype: Stored,
-			
+
+			limit: None,
+
 			ctable: ~[],
 			lltable: ~[],
 			dtable: ~[],
 		}
 	}
 
+	/// Bound the total number of bytes this inflater will produce. A stream
+	/// that tries to expand past ```bytes``` is rejected instead of allocating
+	/// without limit.
+	pub fn set_limit(&mut self, bytes: uint) {
+		self.limit = Some(bytes);
+	}
+
 	pub fn eof(&self) -> bool {
-		self.final && (self.pos as uint == self.buf.len())
+		self.final && !self.reading_block && self.out_pos == self.out.len()
+	}
+
+	//Emit one decoded byte: into the sliding window for later back-references
+	//and onto the pending output queue for the caller.
+	fn emit(&mut self, byte: u8) {
+		self.window[self.wpos] = byte;
+		self.wpos = (self.wpos + 1) % WINDOW_SIZE;
+		self.written += 1;
+
+		self.out.push(byte);
+	}
+
+	//Number of decoded bytes still available for back-references.
+	fn window_len(&self) -> uint {
+		cmp::min(self.written, WINDOW_SIZE as u64) as uint
 	}
 
 	pub fn inner<'a>(&'a mut self) -> &'a mut R {
@@ -108,7 +158,7 @@ impl<R: Reader> Inflater<R> {
 				let _ = try!(self.read_dynamic_tables());
 				self.btype = Compressed;
 			}
-			_ => fail!("reserved block type")
+			_ => return bad_data("reserved block type")
 		}
 
 		Ok(())
@@ -150,7 +200,7 @@ impl<R: Reader> Inflater<R> {
 				}
 				17 => i += 3 + try!(self.h.receive(3)),
 				18 => i += 11 + try!(self.h.receive(7)),
-				_ => fail!("out of range code length code symbol")
+				_ => return bad_data("out of range code length code symbol")
 			}
 		}
 
@@ -179,56 +229,119 @@ impl<R: Reader> Inflater<R> {
 	fn read_stored_block_length(&mut self) -> IoResult<()> {
 		self.h.byte_align();
 
-		let len   = try!(self.h.receive(16));
-		let _nlen = try!(self.h.receive(16));
+		let len  = try!(self.h.receive(16));
+		let nlen = try!(self.h.receive(16));
+
+		//NLEN is the ones-complement of LEN; a mismatch means a corrupt stream.
+		if nlen != !len {
+			return bad_data("stored block length check failed")
+		}
 
 		self.block_length = len as u32;
 
 		Ok(())
 	}
 
-	fn read_stored_block(&mut self) -> IoResult<()> {
-		for _ in range(0, self.block_length) {
+	//Copy raw stored-block bytes into the output until the block is drained or
+	//enough bytes are pending for the current `read`.
+	fn pump_stored(&mut self, want: uint) -> IoResult<()> {
+		while self.block_length > 0 && self.pending() < want {
 			let a = try!(self.h.receive(8));
-			
-			self.buf.push(a as u8);
-			self.h.consume(8);
+			try!(self.check_limit(1));
+			self.emit(a as u8);
+
+			self.block_length -= 1;
 		}
 
-		self.block_length = 0;
-		Ok(()) 
+		if self.block_length == 0 {
+			self.reading_block = false;
+		}
+
+		Ok(())
 	}
 
-	fn read_compressed_block(&mut self) -> IoResult<()> {
-		loop {
+	//Reject output that would grow past a configured bound.
+	fn check_limit(&self, additional: uint) -> IoResult<()> {
+		match self.limit {
+			Some(limit) if (self.written + additional as u64) > limit as u64 =>
+				bad_data("decompressed output exceeds bound"),
+			_ => Ok(()),
+		}
+	}
+
+	//Decode symbols from a compressed block until enough bytes are pending or
+	//the end-of-block symbol is reached.
+	fn pump_compressed(&mut self, want: uint) -> IoResult<()> {
+		while self.pending() < want {
 			let s = try!(self.h.decode_symbol(self.lltable));
 
 			match s {
-				literal @ 0 .. 255 => self.buf.push(literal as u8),
-				256 => break,
+				literal @ 0 .. 255 => {
+					try!(self.check_limit(1));
+					self.emit(literal as u8);
+				}
+				256 => {
+					self.reading_block = false;
+					break
+				}
 				length @ 257 .. 285 => {
 					let length = length - 257;
-					
+
 					let bits = EXTRA_LENGTHS[length];
 					let extra = try!(self.h.receive(bits));
-					
+
 					let length = LENGTHS[length] + extra;
 
 					let distance = try!(self.h.decode_symbol(self.dtable));
 
 					let bits = EXTRA_DISTANCES[distance];
 					let extra = try!(self.h.receive(bits));
-					
+
 					let distance = DISTANCES[distance] + extra;
 
-					let len = self.buf.len();
-					for i in range(0, length) {
-						let s = self.buf[len - distance as uint + i as uint];
-						self.buf.push(s);
+					//A distance may never reach before the start of the window.
+					if distance as uint > self.window_len() {
+						return bad_data("back reference before start of stream")
+					}
+
+					try!(self.check_limit(length as uint));
+
+					//Copy byte-by-byte so overlapping copies (distance < length)
+					//pick up the bytes they just wrote.
+					for _ in range(0, length) {
+						let src = (self.wpos + WINDOW_SIZE - distance as uint) % WINDOW_SIZE;
+						let byte = self.window[src];
+						self.emit(byte);
 					}
 				}
 
-				_ => fail!("out of range symbol")
+				_ => return bad_data("out of range symbol")
+			}
+		}
+
+		Ok(())
+	}
+
+	//Decoded bytes waiting in the output queue.
+	fn pending(&self) -> uint {
+		self.out.len() - self.out_pos
+	}
+
+	//Decode until at least `want` bytes are pending or the stream ends.
+	fn fill(&mut self, want: uint) -> IoResult<()> {
+		while self.pending() < want {
+			if !self.reading_block {
+				if self.final {
+					break
+				}
+
+				let _ = try!(self.read_block_type());
+				self.reading_block = true;
+			}
+
+			match self.btype {
+				Stored     => try!(self.pump_stored(want)),
+				Compressed => try!(self.pump_compressed(want)),
 			}
 		}
 
@@ -238,28 +351,359 @@ impl<R: Reader> Inflater<R> {
 
 impl<R: Reader> Reader for Inflater<R> {
 	fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
-		if self.pos as uint == self.buf.len() {
-			if self.final {
-				return Err(io::standard_error(io::EndOfFile))
-			}
+		let _ = try!(self.fill(buf.len()));
 
-			let _ = try!(self.read_block_type());
-			let _ = match self.btype {
-				Stored => try!(self.read_stored_block()),
-				Compressed => try!(self.read_compressed_block()) 
-			};
+		if self.pending() == 0 {
+			return Err(io::standard_error(io::EndOfFile))
 		}
 
-		let n = cmp::min(buf.len(), self.buf.len() - self.pos as uint);
+		let n = cmp::min(buf.len(), self.pending());
 		for i in range(0, n) {
-			buf[i] = self.buf[self.pos as uint + i];
+			buf[i] = self.out[self.out_pos + i];
+		}
+
+		self.out_pos += n;
+
+		//Drop the consumed prefix once the queue is emptied.
+		if self.out_pos == self.out.len() {
+			self.out.truncate(0);
+			self.out_pos = 0;
 		}
 
-		self.pos += n as u64;
 		Ok(n)
 	}
 }
 
+static MIN_MATCH: uint = 3;
+static MAX_MATCH: uint = 258;
+static WINDOW_SIZE: uint = 32768;
+static HASH_BITS: uint = 15;
+static HASH_SIZE: uint = 1 << HASH_BITS;
+static MAX_CHAIN: uint = 128;
+
+/// The way a `Deflater` packs its data.
+pub enum CompressionMode {
+	/// Emit a single fixed-Huffman block. Small and fast, no table to ship.
+	FixedHuffman,
+
+	/// Emit the input verbatim in stored blocks; useful for incompressible runs.
+	Stored,
+}
+
+/// A DEFLATE compressor, the counterpart to `Inflater`.
+///
+/// Bytes written to it are buffered and emitted on `finish`. In fixed-Huffman
+/// mode they are run through LZ77 match-finding (a 32 KiB window indexed by a
+/// hash chain over 3-byte sequences) and written as a single fixed-Huffman
+/// block, using the code lengths of `create_fixed_tables` and the
+/// `LENGTHS`/`DISTANCES` symbol tables defined above. In stored mode they are
+/// copied verbatim into stored blocks without match-finding.
+pub struct Deflater<W> {
+	b: BitWriter<W>,
+	mode: CompressionMode,
+
+	input: Vec<u8>,
+
+	ll_codes: Vec<(u16, u8)>,
+	d_codes: Vec<(u16, u8)>,
+}
+
+impl<W: Writer> Deflater<W> {
+	/// Create a new compressor writing a single fixed-Huffman block.
+	pub fn new(w: W) -> Deflater<W> {
+		Deflater::new_with_mode(w, FixedHuffman)
+	}
+
+	/// Create a new compressor using the supplied `mode`.
+	pub fn new_with_mode(w: W, mode: CompressionMode) -> Deflater<W> {
+		let ll_lengths = slice::from_fn(288, |i|
+			if i < 144 { 8u8 }
+			else if i < 256 { 9u8 }
+			else if i < 280 { 7u8 }
+			else { 8u8 }
+		);
+
+		let d_lengths = slice::from_elem(DISTANCECODES as uint, 5u8);
+
+		Deflater {
+			b: BitWriter::new(w),
+			mode: mode,
+
+			input: Vec::new(),
+
+			ll_codes: codes_from_lengths(ll_lengths),
+			d_codes: codes_from_lengths(d_lengths),
+		}
+	}
+
+	/// Flush the buffered input as a final DEFLATE block and pad the last byte.
+	pub fn finish(mut self) -> IoResult<()> {
+		match self.mode {
+			Stored       => try!(self.write_stored()),
+			FixedHuffman => try!(self.write_fixed()),
+		}
+
+		self.b.flush_byte()
+	}
+
+	fn write_stored(&mut self) -> IoResult<()> {
+		let input = self.input.clone();
+
+		//Stored blocks carry at most 65535 bytes each.
+		let mut chunks = input.as_slice().chunks(65535).peekable();
+
+		loop {
+			let chunk = match chunks.next() {
+				Some(c) => c,
+				None    => break,
+			};
+			let final = chunks.peek().is_none();
+
+			let _ = try!(self.b.write_bits(if final {1} else {0}, 1));
+			let _ = try!(self.b.write_bits(0b00, 2));
+			let _ = try!(self.b.align());
+
+			let len = chunk.len() as u16;
+			let _ = try!(self.b.write_bits(len & 0xFF, 8));
+			let _ = try!(self.b.write_bits(len >> 8, 8));
+			let nlen = !len;
+			let _ = try!(self.b.write_bits(nlen & 0xFF, 8));
+			let _ = try!(self.b.write_bits(nlen >> 8, 8));
+
+			for &byte in chunk.iter() {
+				let _ = try!(self.b.write_bits(byte as u16, 8));
+			}
+		}
+
+		//An empty input still needs a final block.
+		if input.len() == 0 {
+			let _ = try!(self.b.write_bits(1, 1));
+			let _ = try!(self.b.write_bits(0b00, 2));
+			let _ = try!(self.b.align());
+			let _ = try!(self.b.write_bits(0, 16));
+			let _ = try!(self.b.write_bits(0xFFFF, 16));
+		}
+
+		Ok(())
+	}
+
+	fn write_fixed(&mut self) -> IoResult<()> {
+		//A single final fixed-Huffman block.
+		let _ = try!(self.b.write_bits(1, 1));
+		let _ = try!(self.b.write_bits(0b01, 2));
+
+		let input = self.input.clone();
+		let data  = input.as_slice();
+
+		let mut head = Vec::from_elem(HASH_SIZE, -1i);
+		let mut prev = Vec::from_elem(data.len(), -1i);
+
+		let mut i = 0u;
+		while i < data.len() {
+			let (length, distance) = self.longest_match(data, head.as_slice(), prev.as_slice(), i);
+
+			if length >= MIN_MATCH {
+				let _ = try!(self.write_length(length));
+				let _ = try!(self.write_distance(distance));
+
+				//Insert every position the match spans into the hash chains.
+				for j in range(i, i + length) {
+					if j + MIN_MATCH <= data.len() {
+						let h = hash(data, j);
+						*prev.get_mut(j) = head.as_slice()[h];
+						*head.get_mut(h) = j as int;
+					}
+				}
+
+				i += length;
+			} else {
+				let _ = try!(self.write_literal(data[i]));
+
+				if i + MIN_MATCH <= data.len() {
+					let h = hash(data, i);
+					*prev.get_mut(i) = head.as_slice()[h];
+					*head.get_mut(h) = i as int;
+				}
+
+				i += 1;
+			}
+		}
+
+		//End of block.
+		self.write_ll_symbol(256)
+	}
+
+	//Walk the hash chain for the 3-byte sequence at `pos`, returning the best
+	//(length, distance) within the window or a length below MIN_MATCH if none.
+	fn longest_match(&self, data: &[u8], head: &[int], prev: &[int], pos: uint) -> (uint, uint) {
+		if pos + MIN_MATCH > data.len() {
+			return (0, 0)
+		}
+
+		let max_len = cmp::min(MAX_MATCH, data.len() - pos);
+		let limit   = if pos > WINDOW_SIZE { pos - WINDOW_SIZE } else { 0 };
+
+		let mut best_len = MIN_MATCH - 1;
+		let mut best_dist = 0u;
+
+		let mut candidate = head[hash(data, pos)];
+		let mut chain = 0u;
+
+		while candidate >= 0 && candidate as uint >= limit && chain < MAX_CHAIN {
+			let c = candidate as uint;
+
+			let mut len = 0u;
+			while len < max_len && data[c + len] == data[pos + len] {
+				len += 1;
+			}
+
+			if len > best_len {
+				best_len = len;
+				best_dist = pos - c;
+
+				if len == max_len {
+					break
+				}
+			}
+
+			candidate = prev[c];
+			chain += 1;
+		}
+
+		(best_len, best_dist)
+	}
+
+	fn write_literal(&mut self, byte: u8) -> IoResult<()> {
+		self.write_ll_symbol(byte as u16)
+	}
+
+	fn write_length(&mut self, length: uint) -> IoResult<()> {
+		let mut i = EXTRA_LENGTHS.len() - 1;
+		while LENGTHS[i] as uint > length {
+			i -= 1;
+		}
+
+		let _ = try!(self.write_ll_symbol(257 + i as u16));
+
+		let extra = length - LENGTHS[i] as uint;
+		self.b.write_bits(extra as u16, EXTRA_LENGTHS[i])
+	}
+
+	fn write_distance(&mut self, distance: uint) -> IoResult<()> {
+		let mut i = EXTRA_DISTANCES.len() - 1;
+		while DISTANCES[i] as uint > distance {
+			i -= 1;
+		}
+
+		let (code, len) = self.d_codes.as_slice()[i];
+		let _ = try!(self.b.write_bits(code, len));
+
+		let extra = distance - DISTANCES[i] as uint;
+		self.b.write_bits(extra as u16, EXTRA_DISTANCES[i])
+	}
+
+	fn write_ll_symbol(&mut self, symbol: u16) -> IoResult<()> {
+		let (code, len) = self.ll_codes.as_slice()[symbol as uint];
+		self.b.write_bits(code, len)
+	}
+}
+
+impl<W: Writer> Writer for Deflater<W> {
+	fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+		self.input.push_all(buf);
+		Ok(())
+	}
+}
+
+fn hash(data: &[u8], pos: uint) -> uint {
+	let a = data[pos] as uint;
+	let b = data[pos + 1] as uint;
+	let c = data[pos + 2] as uint;
+
+	((a << 10) ^ (b << 5) ^ c) & (HASH_SIZE - 1)
+}
+
+//Build the canonical Huffman codes for a set of code lengths and return each
+//code already bit-reversed into the LSB-first order DEFLATE transmits.
+fn codes_from_lengths(lengths: &[u8]) -> Vec<(u16, u8)> {
+	let mut bl_count = slice::from_elem(16, 0u16);
+
+	for &len in lengths.iter() {
+		if len != 0 {
+			bl_count[len as uint] += 1;
+		}
+	}
+
+	let mut code = 0u16;
+	let mut next_code = slice::from_elem(16, 0u16);
+
+	for bits in range(1u, 16) {
+		code = (code + bl_count[bits - 1]) << 1;
+		next_code[bits] = code;
+	}
+
+	let mut out = Vec::from_elem(lengths.len(), (0u16, 0u8));
+
+	for (i, &len) in lengths.iter().enumerate() {
+		if len != 0 {
+			let c = next_code[len as uint];
+			next_code[len as uint] += 1;
+
+			let rev = reverse(c) >> (16 - len) as uint;
+			*out.get_mut(i) = (rev, len);
+		}
+	}
+
+	out
+}
+
+//A bit sink writing least-significant bit first, the mirror of `HuffReader`.
+struct BitWriter<W> {
+	w: W,
+
+	accumulator: u32,
+	nbits: u8,
+}
+
+impl<W: Writer> BitWriter<W> {
+	fn new(w: W) -> BitWriter<W> {
+		BitWriter {w: w, accumulator: 0, nbits: 0}
+	}
+
+	fn write_bits(&mut self, value: u16, n: u8) -> IoResult<()> {
+		self.accumulator |= (value as u32) << self.nbits as uint;
+		self.nbits += n;
+
+		while self.nbits >= 8 {
+			let byte = (self.accumulator & 0xFF) as u8;
+			let _ = try!(self.w.write_u8(byte));
+
+			self.accumulator >>= 8;
+			self.nbits -= 8;
+		}
+
+		Ok(())
+	}
+
+	//Advance to the next byte boundary, flushing any partial byte first (deflate
+	//pads the remaining high bits with zeros) so the bits written so far are not lost.
+	fn align(&mut self) -> IoResult<()> {
+		self.flush_byte()
+	}
+
+	fn flush_byte(&mut self) -> IoResult<()> {
+		if self.nbits > 0 {
+			let byte = (self.accumulator & 0xFF) as u8;
+			let _ = try!(self.w.write_u8(byte));
+
+			self.accumulator = 0;
+			self.nbits = 0;
+		}
+
+		self.w.flush()
+	}
+}
+
 fn reverse(a: u16) -> u16 {
 	let b = (((!0x5555) & a) >> 1) | ((0x5555 & a) << 1);
 	let c = (((!0x3333) & b) >> 2) | ((0x3333 & b) << 2);
@@ -390,14 +834,14 @@ impl<R: Reader> HuffReader<R> {
 
 				Table(mask, ref a) => {
 					let index = (self.bits >> TABLESIZE) & mask as u32;
-					
+
 					match a[index] {
 						Symbol(val, size) => (val, size + TABLESIZE),
-						_ 				  => fail!("bad huffman code")
+						_ 				  => return bad_data("bad huffman code")
 					}
 				}
 
-				Nothing => fail!("bad huffman code")
+				Nothing => return bad_data("bad huffman code")
 			};
 			
 			if size <= self.num_bits {
@@ -0,0 +1,197 @@
+use std::io;
+use std::io::IoResult;
+
+/// An LZW decompressor, as used by GIF and TIFF.
+///
+/// The two formats differ only in how they pack the variable-width codes into
+/// the byte stream: GIF packs least-significant-bit first (like the DEFLATE
+/// `HuffReader`), TIFF most-significant-bit first. The decoder is parameterised
+/// by the minimum code width and that bit order; the dictionary logic is shared.
+pub struct LzwReader<R> {
+	b: BitReader<R>,
+
+	min_code_size: u8,
+	early_change: bool,
+
+	decoded: Option<Vec<u8>>,
+	pos: uint,
+}
+
+impl<R: Reader> LzwReader<R> {
+	/// Create a new reader decoding from ```r```.
+	///
+	/// ```min_code_size``` is the bit width of the literal codes (8 for TIFF, the
+	/// value stored in the stream for GIF); the first codes read are one bit wider. Set
+	/// ```msb_first``` for TIFF packing, clear it for GIF. Set ```early_change```
+	/// for TIFF's convention of widening the code one entry sooner; clear it for GIF.
+	pub fn new(r: R, min_code_size: u8, msb_first: bool, early_change: bool) -> LzwReader<R> {
+		LzwReader {
+			b: BitReader::new(r, msb_first),
+
+			min_code_size: min_code_size,
+			early_change: early_change,
+
+			decoded: None,
+			pos: 0,
+		}
+	}
+
+	fn decode(&mut self) -> IoResult<()> {
+		let clear = 1u16 << self.min_code_size as uint;
+		let eoi   = clear + 1;
+
+		let mut dict: Vec<Vec<u8>> = Vec::new();
+		let mut code_size = self.min_code_size + 1;
+		reset_dict(&mut dict, clear);
+
+		let mut out = Vec::new();
+		let mut prev: Option<Vec<u8>> = None;
+
+		loop {
+			let code = match try!(self.b.read_bits(code_size)) {
+				Some(c) => c,
+				None    => break,
+			};
+
+			if code == clear {
+				reset_dict(&mut dict, clear);
+				code_size = self.min_code_size + 1;
+				prev = None;
+				continue
+			}
+
+			if code == eoi {
+				break
+			}
+
+			let entry = if (code as uint) < dict.len() {
+				dict.get(code as uint).clone()
+			} else if code as uint == dict.len() {
+				//The code refers to the entry we are about to add, so it is the
+				//previous string followed by its own first byte.
+				match prev {
+					Some(ref p) => {
+						let mut e = p.clone();
+						let first = (*p)[0];
+						e.push(first);
+						e
+					}
+					None => return Err(io::standard_error(io::InvalidInput)),
+				}
+			} else {
+				return Err(io::standard_error(io::InvalidInput))
+			};
+
+			out.push_all(entry.as_slice());
+
+			match prev {
+				Some(p) => {
+					let mut ne = p;
+					ne.push(entry[0]);
+					dict.push(ne);
+
+					let early = if self.early_change {1} else {0};
+					if dict.len() + early == (1 << code_size as uint) && code_size < 12 {
+						code_size += 1;
+					}
+				}
+				None => {}
+			}
+
+			prev = Some(entry);
+		}
+
+		self.decoded = Some(out);
+
+		Ok(())
+	}
+}
+
+//Restore the dictionary to its single-byte entries plus the two reserved codes.
+fn reset_dict(dict: &mut Vec<Vec<u8>>, clear: u16) {
+	dict.truncate(0);
+
+	for i in range(0, clear) {
+		dict.push(vec![i as u8]);
+	}
+
+	//Placeholders for the Clear and End-of-Information codes.
+	dict.push(Vec::new());
+	dict.push(Vec::new());
+}
+
+impl<R: Reader> Reader for LzwReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+		if self.decoded.is_none() {
+			try!(self.decode());
+		}
+
+		let data = self.decoded.get_ref();
+
+		if self.pos == data.len() {
+			return Err(io::standard_error(io::EndOfFile))
+		}
+
+		let n = ::std::cmp::min(buf.len(), data.len() - self.pos);
+		for i in range(0, n) {
+			buf[i] = (*data)[self.pos + i];
+		}
+
+		self.pos += n;
+
+		Ok(n)
+	}
+}
+
+//A variable-width code reader supporting both bit orders.
+struct BitReader<R> {
+	r: R,
+
+	bits: u32,
+	num_bits: u8,
+	msb_first: bool,
+}
+
+impl<R: Reader> BitReader<R> {
+	fn new(r: R, msb_first: bool) -> BitReader<R> {
+		BitReader {r: r, bits: 0, num_bits: 0, msb_first: msb_first}
+	}
+
+	//Read the next `n`-bit code, or `None` once the stream runs out.
+	fn read_bits(&mut self, n: u8) -> IoResult<Option<u16>> {
+		while self.num_bits < n {
+			match self.r.read_u8() {
+				Ok(byte) => {
+					if self.msb_first {
+						self.bits = (self.bits << 8) | byte as u32;
+					} else {
+						self.bits |= (byte as u32) << self.num_bits as uint;
+					}
+					self.num_bits += 8;
+				}
+
+				Err(ref e) if e.kind == io::EndOfFile => return Ok(None),
+				Err(e) => return Err(e),
+			}
+		}
+
+		let mask = (1u32 << n as uint) - 1;
+
+		let value = if self.msb_first {
+			self.num_bits -= n;
+			(self.bits >> self.num_bits as uint) & mask
+		} else {
+			let v = self.bits & mask;
+			self.bits >>= n as uint;
+			self.num_bits -= n;
+			v
+		};
+
+		//Keep only the still-unconsumed low bits around for MSB-first order.
+		if self.msb_first {
+			self.bits &= (1u32 << self.num_bits as uint) - 1;
+		}
+
+		Ok(Some(value as u16))
+	}
+}
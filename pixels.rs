@@ -7,6 +7,27 @@ use sample;
 use colortype;
 use colortype::ColorType;
 
+/// The value a channel takes when fully saturated / fully opaque.
+///
+/// For the integer primitives this is `Bounded::max_value()`, but for
+/// floating point samples \"full\" is `1.0`, not `f32::MAX`, since those
+/// buffers hold linear-light values in the `[0, 1]` range.
+pub trait ChannelMax {
+	fn channel_max() -> Self;
+}
+
+impl ChannelMax for u8 {
+	fn channel_max() -> u8 { Bounded::max_value() }
+}
+
+impl ChannelMax for u16 {
+	fn channel_max() -> u16 { Bounded::max_value() }
+}
+
+impl ChannelMax for f32 {
+	fn channel_max() -> f32 { 1.0 }
+}
+
 ///A type to hold a grayscale pixel
 #[packed]
 #[deriving(Default, PartialEq, Clone, Show, Copy)]
@@ -77,6 +98,42 @@ impl<T: Primitive> Rgba<T> {
 	}
 }
 
+/// A type to hold a BGR pixel, i.e. an RGB pixel with the red and blue
+/// channels stored in reversed order as many framebuffers deliver them.
+#[packed]
+#[deriving(Default, PartialEq, Clone, Show, Copy)]
+pub struct Bgr<T>(pub T, pub T, pub T);
+
+impl<T: Primitive> Bgr<T> {
+	/// Returns the channels of this pixel as a tuple in ```(b, g, r)``` order
+	pub fn channels(&self) -> (T, T, T) {
+		match *self {
+			Bgr(b, g, r) => (b, g, r)
+		}
+	}
+}
+
+/// A type to hold a BGR pixel with an alpha channel
+#[packed]
+#[deriving(Default, PartialEq, Clone, Show, Copy)]
+pub struct Bgra<T>(pub T, pub T, pub T, pub T);
+
+impl<T: Primitive> Bgra<T> {
+	/// Returns the channels of this pixel as a tuple in ```(b, g, r, a)``` order
+	pub fn channels(&self) -> (T, T, T, T) {
+		match *self {
+			Bgra(b, g, r, a) => (b, g, r, a)
+		}
+	}
+
+	/// Returns the alpha channel of this pixel
+	pub fn alpha(&self) -> T {
+		match *self {
+			Bgra(_, _, _, a) => a
+		}
+	}
+}
+
 /// A trait that all pixels implement.
 pub trait Pixel<T> {
 	fn from_channels(&self, a: T, b: T, c: T, d: T) -> Self;
@@ -108,7 +165,7 @@ pub trait Pixel<T> {
 	fn channels4(&self) -> (T, T, T, T);
 }
 
-impl<T: Primitive> Pixel<T> for Rgb<T> {
+impl<T: Primitive + ChannelMax> Pixel<T> for Rgb<T> {
 	fn from_channels(&self, a: T, b: T, c: T, _: T) -> Rgb<T> {
 		Rgb(a, b, c)
 	}
@@ -126,7 +183,7 @@ impl<T: Primitive> Pixel<T> for Rgb<T> {
 	fn to_luma_alpha(&self) -> LumaA<T> {
 		let l = self.to_luma().channel();
 
-		LumaA(l, Bounded::max_value())
+		LumaA(l, ChannelMax::channel_max())
 	}
 
 	fn to_rgb(&self) -> Rgb<T> {
@@ -136,13 +193,13 @@ impl<T: Primitive> Pixel<T> for Rgb<T> {
 	fn to_rgba(&self) -> Rgba<T> {
 		let (r, g, b) = self.channels();
 
-		Rgba(r, g, b, Bounded::max_value())
+		Rgba(r, g, b, ChannelMax::channel_max())
 	}
 
 	fn invert(&mut self) {
 		let (r, g, b) = self.channels();
 
-		let max: T = Bounded::max_value();
+		let max: T = ChannelMax::channel_max();
 
 		let r1 = max - r;
 		let g1 = max - g;
@@ -175,11 +232,11 @@ impl<T: Primitive> Pixel<T> for Rgb<T> {
 	fn channels4(&self) ->(T, T, T, T) {
 		let (r, g, b) = self.channels();
 
-		(r, g, b, Bounded::max_value())
+		(r, g, b, ChannelMax::channel_max())
 	}
 }
 
-impl<T: Primitive> Pixel<T> for Rgba<T> {
+impl<T: Primitive + ChannelMax> Pixel<T> for Rgba<T> {
 	fn from_channels(&self, a: T, b: T, c: T, d: T) -> Rgba<T> {
 		Rgba(a, b, c, d)
 	}
@@ -209,7 +266,7 @@ impl<T: Primitive> Pixel<T> for Rgba<T> {
 		let (r, g, b) = self.to_rgb().channels();
 		let a = self.alpha();
 
-		let max: T = Bounded::max_value();
+		let max: T = ChannelMax::channel_max();
 
 		*self = Rgba(max - r, max - g, max - b, a)
 	}
@@ -242,7 +299,118 @@ impl<T: Primitive> Pixel<T> for Rgba<T> {
 	}
 }
 
-impl<T: Primitive> Pixel<T> for Luma<T> {
+impl<T: Primitive + ChannelMax> Pixel<T> for Bgr<T> {
+	fn from_channels(&self, a: T, b: T, c: T, _: T) -> Bgr<T> {
+		Bgr(c, b, a)
+	}
+
+	fn to_luma(&self) -> Luma<T> {
+		self.to_rgb().to_luma()
+	}
+
+	fn to_luma_alpha(&self) -> LumaA<T> {
+		let l = self.to_luma().channel();
+
+		LumaA(l, ChannelMax::channel_max())
+	}
+
+	fn to_rgb(&self) -> Rgb<T> {
+		let (b, g, r) = self.channels();
+
+		Rgb(r, g, b)
+	}
+
+	fn to_rgba(&self) -> Rgba<T> {
+		let (b, g, r) = self.channels();
+
+		Rgba(r, g, b, ChannelMax::channel_max())
+	}
+
+	fn invert(&mut self) {
+		let (b, g, r) = self.channels();
+
+		let max: T = ChannelMax::channel_max();
+
+		*self = Bgr(max - b, max - g, max - r)
+	}
+
+	fn map(&self, f: |a: T| -> T) -> Bgr<T> {
+		let (b, g, r) = self.channels();
+
+		Bgr(f(b), f(g), f(r))
+	}
+
+	fn map2(&self, other: Bgr<T>, f: |a: T, b: T| -> T) -> Bgr<T> {
+		let (b1, g1, r1) = self.channels();
+		let (b2, g2, r2) = other.channels();
+
+		Bgr(f(b1, b2), f(g1, g2), f(r1, r2))
+	}
+
+	fn channels4(&self) ->(T, T, T, T) {
+		let (b, g, r) = self.channels();
+
+		(r, g, b, ChannelMax::channel_max())
+	}
+}
+
+impl<T: Primitive + ChannelMax> Pixel<T> for Bgra<T> {
+	fn from_channels(&self, a: T, b: T, c: T, d: T) -> Bgra<T> {
+		Bgra(c, b, a, d)
+	}
+
+	fn to_luma(&self) -> Luma<T> {
+		self.to_rgb().to_luma()
+	}
+
+	fn to_luma_alpha(&self) -> LumaA<T> {
+		let l = self.to_luma().channel();
+		let a = self.alpha();
+
+		LumaA(l, a)
+	}
+
+	fn to_rgb(&self) -> Rgb<T> {
+		let (b, g, r, _) = self.channels();
+
+		Rgb(r, g, b)
+	}
+
+	fn to_rgba(&self) -> Rgba<T> {
+		let (b, g, r, a) = self.channels();
+
+		Rgba(r, g, b, a)
+	}
+
+	fn invert(&mut self) {
+		let (b, g, r, a) = self.channels();
+
+		let max: T = ChannelMax::channel_max();
+
+		*self = Bgra(max - b, max - g, max - r, a)
+	}
+
+	fn map(&self, f: |a: T| -> T) -> Bgra<T> {
+		let (b, g, r, a) = self.channels();
+
+		Bgra(f(b), f(g), f(r), a)
+	}
+
+	fn map2(&self, other: Bgra<T>, f: |a: T, b: T| -> T) -> Bgra<T> {
+		let (b1, g1, r1, a1) = self.channels();
+		let (b2, g2, r2, _)  = other.channels();
+
+		Bgra(f(b1, b2), f(g1, g2), f(r1, r2), a1)
+	}
+
+	fn channels4(&self) ->(T, T, T, T) {
+		let (b, g, r, a) = self.channels();
+
+		(r, g, b, a)
+	}
+}
+
+impl<T: Primitive + ChannelMax> Pixel<T> for Luma<T> {
 	fn from_channels(&self, a: T, _: T, _: T, _: T) -> Luma<T> {
 		Luma(a)
 	}
@@ -254,7 +422,7 @@ impl<T: Primitive> Pixel<T> for Luma<T> {
 	fn to_luma_alpha(&self) -> LumaA<T> {
 		let l = self.channel();
 
-		LumaA(l, Bounded::max_value())
+		LumaA(l, ChannelMax::channel_max())
 	}
 
 	fn to_rgb(&self) -> Rgb<T> {
@@ -268,11 +436,11 @@ impl<T: Primitive> Pixel<T> for Luma<T> {
 	fn to_rgba(&self) -> Rgba<T> {
 		let (r, g, b) = self.to_rgb().channels();
 
-		Rgba(r, g, b, Bounded::max_value())
+		Rgba(r, g, b, ChannelMax::channel_max())
 	}
 
 	fn invert(&mut self) {
-		let max: T = Bounded::max_value();
+		let max: T = ChannelMax::channel_max();
 		let l1 = max - self.channel();
 
 		*self = Luma(l1)
@@ -296,13 +464,13 @@ impl<T: Primitive> Pixel<T> for Luma<T> {
 
 	fn channels4(&self) ->(T, T, T, T) {
 		let l = self.channel();
-		let max: T = Bounded::max_value();
+		let max: T = ChannelMax::channel_max();
 
 		(l, max.clone(), max.clone(), max.clone())
 	}
 }
 
-impl<T: Primitive> Pixel<T> for LumaA<T> {
+impl<T: Primitive + ChannelMax> Pixel<T> for LumaA<T> {
 	fn from_channels(&self, a: T, b: T, _: T, _: T) -> LumaA<T> {
 		LumaA(a, b)
 	}
@@ -335,7 +503,7 @@ impl<T: Primitive> Pixel<T> for LumaA<T> {
 		let l = self.to_luma().channel();
 		let a  = self.alpha();
 
-		let max: T = Bounded::max_value();
+		let max: T = ChannelMax::channel_max();
 
 		*self = LumaA(max - l, a)
 	}
@@ -359,7 +527,7 @@ impl<T: Primitive> Pixel<T> for LumaA<T> {
 
 	fn channels4(&self) ->(T, T, T, T) {
 		let (l, a) = self.channels();
-		let max: T = Bounded::max_value();
+		let max: T = ChannelMax::channel_max();
 
 		(l, a, max.clone(), max.clone())
 	}
@@ -370,6 +538,17 @@ pub enum PixelBufSlice<'a> {
 	LumaA8Slice(&'a [LumaA<u8>]),
 	Rgb8Slice(&'a [Rgb<u8>]),
 	Rgba8Slice(&'a [Rgba<u8>]),
+
+	Luma16Slice(&'a [Luma<u16>]),
+	LumaA16Slice(&'a [LumaA<u16>]),
+	Rgb16Slice(&'a [Rgb<u16>]),
+	Rgba16Slice(&'a [Rgba<u16>]),
+
+	Bgr8Slice(&'a [Bgr<u8>]),
+	Bgra8Slice(&'a [Bgra<u8>]),
+
+	Rgb32FSlice(&'a [Rgb<f32>]),
+	Rgba32FSlice(&'a [Rgba<f32>]),
 }
 
 pub enum PixelBufMutSlice<'a> {
@@ -377,22 +556,48 @@ pub enum PixelBufMutSlice<'a> {
 	LumaA8MutSlice(&'a mut [LumaA<u8>]),
 	Rgb8MutSlice(&'a mut [Rgb<u8>]),
 	Rgba8MutSlice(&'a mut [Rgba<u8>]),
+
+	Luma16MutSlice(&'a mut [Luma<u16>]),
+	LumaA16MutSlice(&'a mut [LumaA<u16>]),
+	Rgb16MutSlice(&'a mut [Rgb<u16>]),
+	Rgba16MutSlice(&'a mut [Rgba<u16>]),
+
+	Bgr8MutSlice(&'a mut [Bgr<u8>]),
+	Bgra8MutSlice(&'a mut [Bgra<u8>]),
+
+	Rgb32FMutSlice(&'a mut [Rgb<f32>]),
+	Rgba32FMutSlice(&'a mut [Rgba<f32>]),
 }
 
 /// An abstraction over a vector of pixel types
 #[deriving(Clone, Show, PartialEq)]
 pub enum PixelBuf {
 	Luma8(Vec<Luma<u8>>),
-	//Luma16(Vec<Luma<u16>>),
+	Luma16(Vec<Luma<u16>>),
 
 	LumaA8(Vec<LumaA<u8>>),
-	//LumaA16(Vec<LumaA<u16>>),
+	LumaA16(Vec<LumaA<u16>>),
 
 	Rgb8(Vec<Rgb<u8>>),
-	//Rgb16(Vec<Rgb<u16>>),
+	Rgb16(Vec<Rgb<u16>>),
 
 	Rgba8(Vec<Rgba<u8>>),
-	//Rgba16(Vec<Rgba<u16>>),
+	Rgba16(Vec<Rgba<u16>>),
+
+	Bgr8(Vec<Bgr<u8>>),
+	Bgra8(Vec<Bgra<u8>>),
+
+	Rgb32F(Vec<Rgb<f32>>),
+	Rgba32F(Vec<Rgba<f32>>),
+
+	/// A palettized buffer: each index selects a colour from ```palette```.
+	/// An optional ```transparency``` table assigns a per-index alpha and,
+	/// when present, promotes an expansion to ```Rgba8```.
+	Indexed8 {
+		indices:      Vec<u8>,
+		palette:      Vec<Rgb<u8>>,
+		transparency: Option<Vec<u8>>,
+	},
 }
 
 impl PixelBuf {
@@ -424,12 +629,53 @@ impl PixelBuf {
 		}
 	}
 
+	pub fn as_bgr8<'a>(&'a self) -> Option<&'a [Bgr<u8>]> {
+		match *self {
+			Bgr8(ref p) => Some(p.as_slice()),
+			_ 	    => None
+		}
+	}
+
+	pub fn as_bgra8<'a>(&'a self) -> Option<&'a [Bgra<u8>]> {
+		match *self {
+			Bgra8(ref p) => Some(p.as_slice()),
+			_ 	     => None
+		}
+	}
+
+	pub fn as_rgb32f<'a>(&'a self) -> Option<&'a [Rgb<f32>]> {
+		match *self {
+			Rgb32F(ref p) => Some(p.as_slice()),
+			_ 	      => None
+		}
+	}
+
+	pub fn as_rgba32f<'a>(&'a self) -> Option<&'a [Rgba<f32>]> {
+		match *self {
+			Rgba32F(ref p) => Some(p.as_slice()),
+			_ 	       => None
+		}
+	}
+
 	pub fn as_slice<'a>(&'a self) -> PixelBufSlice<'a> {
 		match *self {
 			Luma8(ref p)  => Luma8Slice(p.as_slice()),
 			LumaA8(ref p) => LumaA8Slice(p.as_slice()),
 			Rgb8(ref p)   => Rgb8Slice(p.as_slice()),
 			Rgba8(ref p)  => Rgba8Slice(p.as_slice()),
+
+			Luma16(ref p)  => Luma16Slice(p.as_slice()),
+			LumaA16(ref p) => LumaA16Slice(p.as_slice()),
+			Rgb16(ref p)   => Rgb16Slice(p.as_slice()),
+			Rgba16(ref p)  => Rgba16Slice(p.as_slice()),
+
+			Bgr8(ref p)   => Bgr8Slice(p.as_slice()),
+			Bgra8(ref p)  => Bgra8Slice(p.as_slice()),
+
+			Rgb32F(ref p)  => Rgb32FSlice(p.as_slice()),
+			Rgba32F(ref p) => Rgba32FSlice(p.as_slice()),
+
+			Indexed8 { .. } => fail!("indexed buffers have no homogeneous pixel slice; expand_to_rgb first"),
 		}
 	}
 
@@ -439,6 +685,19 @@ impl PixelBuf {
 			LumaA8(ref mut p) => LumaA8MutSlice(p.as_mut_slice()),
 			Rgb8(ref mut p)   => Rgb8MutSlice(p.as_mut_slice()),
 			Rgba8(ref mut p)  => Rgba8MutSlice(p.as_mut_slice()),
+
+			Luma16(ref mut p)  => Luma16MutSlice(p.as_mut_slice()),
+			LumaA16(ref mut p) => LumaA16MutSlice(p.as_mut_slice()),
+			Rgb16(ref mut p)   => Rgb16MutSlice(p.as_mut_slice()),
+			Rgba16(ref mut p)  => Rgba16MutSlice(p.as_mut_slice()),
+
+			Bgr8(ref mut p)   => Bgr8MutSlice(p.as_mut_slice()),
+			Bgra8(ref mut p)  => Bgra8MutSlice(p.as_mut_slice()),
+
+			Rgb32F(ref mut p)  => Rgb32FMutSlice(p.as_mut_slice()),
+			Rgba32F(ref mut p) => Rgba32FMutSlice(p.as_mut_slice()),
+
+			Indexed8 { .. } => fail!("indexed buffers have no homogeneous pixel slice; expand_to_rgb first"),
 		}
 	}
 
@@ -482,6 +741,89 @@ impl PixelBuf {
 				Some(LumaA8(p))
 			}
 
+			colortype::RGB(16) => {
+				let p = buf.as_slice()
+					   .chunks(6)
+					   .map(|a| Rgb::<u16>(be_u16(a.slice(0, 2)),
+							       be_u16(a.slice(2, 4)),
+							       be_u16(a.slice(4, 6))))
+					   .collect();
+
+				Some(Rgb16(p))
+			}
+
+			colortype::RGBA(16) => {
+				let p = buf.as_slice()
+					   .chunks(8)
+					   .map(|a| Rgba::<u16>(be_u16(a.slice(0, 2)),
+								be_u16(a.slice(2, 4)),
+								be_u16(a.slice(4, 6)),
+								be_u16(a.slice(6, 8))))
+					   .collect();
+
+				Some(Rgba16(p))
+			}
+
+			colortype::Grey(16) => {
+				let p = buf.as_slice()
+					   .chunks(2)
+					   .map(|a| Luma::<u16>(be_u16(a)))
+					   .collect();
+
+				Some(Luma16(p))
+			}
+
+			colortype::GreyA(16) => {
+				let p = buf.as_slice()
+					   .chunks(4)
+					   .map(|a| LumaA::<u16>(be_u16(a.slice(0, 2)),
+								 be_u16(a.slice(2, 4))))
+					   .collect();
+
+				Some(LumaA16(p))
+			}
+
+			colortype::BGR(8) => {
+				let p = buf.as_slice()
+					   .chunks(3)
+					   .map(|a| Bgr::<u8>(a[0], a[1], a[2]))
+					   .collect();
+
+				Some(Bgr8(p))
+			}
+
+			colortype::BGRA(8) => {
+				let p = buf.as_slice()
+					   .chunks(4)
+					   .map(|a| Bgra::<u8>(a[0], a[1], a[2], a[3]))
+					   .collect();
+
+				Some(Bgra8(p))
+			}
+
+			colortype::RGB(32) => {
+				let p = buf.as_slice()
+					   .chunks(12)
+					   .map(|a| Rgb::<f32>(ne_f32(a.slice(0, 4)),
+							       ne_f32(a.slice(4, 8)),
+							       ne_f32(a.slice(8, 12))))
+					   .collect();
+
+				Some(Rgb32F(p))
+			}
+
+			colortype::RGBA(32) => {
+				let p = buf.as_slice()
+					   .chunks(16)
+					   .map(|a| Rgba::<f32>(ne_f32(a.slice(0, 4)),
+								ne_f32(a.slice(4, 8)),
+								ne_f32(a.slice(8, 12)),
+								ne_f32(a.slice(12, 16))))
+					   .collect();
+
+				Some(Rgba32F(p))
+			}
+
 			_ => None
 		}
 	}
@@ -523,10 +865,151 @@ impl PixelBuf {
 					r.push(alpha);
 				}
 			}
+
+			Luma16(ref a) => {
+				for &i in a.iter() {
+					push_be_u16(&mut r, i.channel());
+				}
+			}
+
+			LumaA16(ref a) => {
+				for &i in a.iter() {
+					let (l, alpha) = i.channels();
+					push_be_u16(&mut r, l);
+					push_be_u16(&mut r, alpha);
+				}
+			}
+
+			Rgb16(ref a)  => {
+				for &i in a.iter() {
+					let (red, g, b) = i.channels();
+					push_be_u16(&mut r, red);
+					push_be_u16(&mut r, g);
+					push_be_u16(&mut r, b);
+				}
+			}
+
+			Rgba16(ref a) => {
+				for &i in a.iter() {
+					let (red, g, b, alpha) = i.channels();
+					push_be_u16(&mut r, red);
+					push_be_u16(&mut r, g);
+					push_be_u16(&mut r, b);
+					push_be_u16(&mut r, alpha);
+				}
+			}
+
+			Bgr8(ref a)  => {
+				for &i in a.iter() {
+					let (b, g, red) = i.channels();
+					r.push(b);
+					r.push(g);
+					r.push(red);
+				}
+			}
+
+			Bgra8(ref a) => {
+				for &i in a.iter() {
+					let (b, g, red, alpha) = i.channels();
+					r.push(b);
+					r.push(g);
+					r.push(red);
+					r.push(alpha);
+				}
+			}
+
+			Rgb32F(ref a)  => {
+				for &i in a.iter() {
+					let (red, g, b) = i.channels();
+					push_ne_f32(&mut r, red);
+					push_ne_f32(&mut r, g);
+					push_ne_f32(&mut r, b);
+				}
+			}
+
+			Rgba32F(ref a) => {
+				for &i in a.iter() {
+					let (red, g, b, alpha) = i.channels();
+					push_ne_f32(&mut r, red);
+					push_ne_f32(&mut r, g);
+					push_ne_f32(&mut r, b);
+					push_ne_f32(&mut r, alpha);
+				}
+			}
+
+			// An indexed buffer serializes as its raw index stream.
+			Indexed8 { ref indices, .. } => {
+				r.push_all(indices.as_slice());
+			}
 		}
 
 		r
 	}
+
+	/// Expand a palettized buffer into a direct-colour ```PixelBuf```.
+	///
+	/// Each index is mapped through ```palette```; if a ```transparency```
+	/// table is present the result is an ```Rgba8``` buffer carrying the
+	/// per-index alpha, otherwise an ```Rgb8``` buffer. Non-indexed buffers
+	/// are returned unchanged.
+	pub fn expand_to_rgb(&self) -> PixelBuf {
+		match *self {
+			Indexed8 { ref indices, ref palette, transparency: Some(ref t) } => {
+				let p = indices.iter().map(|&i| {
+					//A corrupt colour table can carry indices past its end; treat
+					//those as opaque black rather than panicking.
+					let (r, g, b) = if (i as uint) < palette.len() {
+						palette[i as uint].channels()
+					} else {
+						(0, 0, 0)
+					};
+					let a = if (i as uint) < t.len() { t[i as uint] } else { 255 };
+					Rgba::<u8>(r, g, b, a)
+				}).collect();
+
+				Rgba8(p)
+			}
+
+			Indexed8 { ref indices, ref palette, transparency: None } => {
+				let p = indices.iter().map(|&i| {
+					if (i as uint) < palette.len() {
+						palette[i as uint].clone()
+					} else {
+						Rgb::<u8>(0, 0, 0)
+					}
+				}).collect();
+
+				Rgb8(p)
+			}
+
+			_ => self.clone()
+		}
+	}
+}
+
+/// Read a big-endian ```u16``` sample from the first two bytes of ```a```.
+fn be_u16(a: &[u8]) -> u16 {
+	(a[0] as u16 << 8) | a[1] as u16
+}
+
+/// Append ```s``` to ```buf``` as two big-endian bytes.
+fn push_be_u16(buf: &mut Vec<u8>, s: u16) {
+	buf.push((s >> 8) as u8);
+	buf.push((s & 0xFF) as u8);
+}
+
+/// Read a native-endian ```f32``` sample from the first four bytes of ```a```.
+fn ne_f32(a: &[u8]) -> f32 {
+	let bytes = [a[0], a[1], a[2], a[3]];
+
+	unsafe { std::mem::transmute(bytes) }
+}
+
+/// Append ```s``` to ```buf``` as four native-endian bytes.
+fn push_ne_f32(buf: &mut Vec<u8>, s: f32) {
+	let bytes: [u8, ..4] = unsafe { std::mem::transmute(s) };
+
+	buf.push_all(bytes.as_slice());
 }
 
 /// Convert the ```PixelBuf``` pixels to graysacle
@@ -548,6 +1031,55 @@ pub fn grayscale(pixels: &PixelBuf) -> PixelBuf {
 			let n = p.iter().map(|i| i.to_luma()).collect();
 			Luma8(n)
 		}
+
+		Luma16(_)      => pixels.clone(),
+
+		LumaA16(ref p) => {
+			let n = p.iter().map(|i| i.to_luma()).collect();
+			Luma16(n)
+		}
+
+		Rgb16(ref p)   => {
+			let n = p.iter().map(|i| i.to_luma()).collect();
+			Luma16(n)
+		}
+
+		Rgba16(ref p)  => {
+			let n = p.iter().map(|i| i.to_luma()).collect();
+			Luma16(n)
+		}
+
+		Bgr8(ref p)   => {
+			let n = p.iter().map(|i| i.to_luma()).collect();
+			Luma8(n)
+		}
+
+		Bgra8(ref p)  => {
+			let n = p.iter().map(|i| i.to_luma()).collect();
+			Luma8(n)
+		}
+
+		// There is no floating point luma buffer, so represent the
+		// grayscale result as an ```Rgb32F``` with equal channels.
+		Rgb32F(ref p)  => {
+			let n = p.iter().map(|i| {
+				let l = i.to_luma().channel();
+				Rgb(l, l, l)
+			}).collect();
+			Rgb32F(n)
+		}
+
+		Rgba32F(ref p) => {
+			let n = p.iter().map(|i| {
+				let l = i.to_luma().channel();
+				let a = i.alpha();
+				Rgba(l, l, l, a)
+			}).collect();
+			Rgba32F(n)
+		}
+
+		// Sampling between palette indices is meaningless, so expand first.
+		Indexed8 { .. } => grayscale(&pixels.expand_to_rgb()),
 	}
 }
 
@@ -567,6 +1099,20 @@ pub fn invert(pixels: &mut PixelBuf) {
 		LumaA8(ref mut p) => invert_pixels(p.as_mut_slice()),
 		Rgb8(ref mut p)   => invert_pixels(p.as_mut_slice()),
 		Rgba8(ref mut p)  => invert_pixels(p.as_mut_slice()),
+
+		Luma16(ref mut p)  => invert_pixels(p.as_mut_slice()),
+		LumaA16(ref mut p) => invert_pixels(p.as_mut_slice()),
+		Rgb16(ref mut p)   => invert_pixels(p.as_mut_slice()),
+		Rgba16(ref mut p)  => invert_pixels(p.as_mut_slice()),
+
+		Bgr8(ref mut p)   => invert_pixels(p.as_mut_slice()),
+		Bgra8(ref mut p)  => invert_pixels(p.as_mut_slice()),
+
+		Rgb32F(ref mut p)  => invert_pixels(p.as_mut_slice()),
+		Rgba32F(ref mut p) => invert_pixels(p.as_mut_slice()),
+
+		// Inverting an indexed buffer inverts its palette entries.
+		Indexed8 { ref mut palette, .. } => invert_pixels(palette.as_mut_slice()),
 	}
 }
 
@@ -580,6 +1126,12 @@ pub fn resize(pixels:  &PixelBuf,
 	      nheight: u32,
 	      filter:  sample::FilterType) -> PixelBuf {
 
+	// Palette indices cannot be interpolated, so expand before sampling.
+	match *pixels {
+		Indexed8 { .. } => return resize(&pixels.expand_to_rgb(), width, height, nwidth, nheight, filter),
+		_ 	        => {}
+	}
+
 	let method = match filter {
 		sample::Nearest    => 	sample::Filter {
 						kernel:  |x| sample::box_kernel(x),
@@ -608,6 +1160,19 @@ pub fn resize(pixels:  &PixelBuf,
 		LumaA8(ref p) => LumaA8(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
 		Rgb8(ref p)   => Rgb8(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
 		Rgba8(ref p)  => Rgba8(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
+
+		Luma16(ref p)  => Luma16(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
+		LumaA16(ref p) => LumaA16(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
+		Rgb16(ref p)   => Rgb16(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
+		Rgba16(ref p)  => Rgba16(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
+
+		Bgr8(ref p)   => Bgr8(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
+		Bgra8(ref p)  => Bgra8(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
+
+		Rgb32F(ref p)  => Rgb32F(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
+		Rgba32F(ref p) => Rgba32F(sample::vertical_sample(p.as_slice(), height, width, nheight, method)),
+
+		Indexed8 { .. } => unreachable!(), // expanded above
 	};
 
 	let method = match filter {
@@ -638,6 +1203,19 @@ pub fn resize(pixels:  &PixelBuf,
 		LumaA8(ref p) => LumaA8(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
 		Rgb8(ref p)   => Rgb8(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
 		Rgba8(ref p)  => Rgba8(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
+
+		Luma16(ref p)  => Luma16(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
+		LumaA16(ref p) => LumaA16(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
+		Rgb16(ref p)   => Rgb16(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
+		Rgba16(ref p)  => Rgba16(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
+
+		Bgr8(ref p)   => Bgr8(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
+		Bgra8(ref p)  => Bgra8(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
+
+		Rgb32F(ref p)  => Rgb32F(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
+		Rgba32F(ref p) => Rgba32F(sample::horizontal_sample(p.as_slice(), width, nheight, nwidth, method)),
+
+		Indexed8 { .. } => unreachable!(), // expanded above
 	}
 }
 
@@ -651,6 +1229,12 @@ pub fn blur(pixels:  &PixelBuf,
 	    height:  u32,
 	    sigma:   f32) -> PixelBuf {
 
+	// Palette indices cannot be interpolated, so expand before sampling.
+	match *pixels {
+		Indexed8 { .. } => return blur(&pixels.expand_to_rgb(), width, height, sigma),
+		_ 	        => {}
+	}
+
 	let sigma = if sigma < 0.0 {
 		1.0
 	} else {
@@ -667,6 +1251,19 @@ pub fn blur(pixels:  &PixelBuf,
 		LumaA8(ref p) => LumaA8(sample::vertical_sample(p.as_slice(), height, width, height, method)),
 		Rgb8(ref p)   => Rgb8(sample::vertical_sample(p.as_slice(), height, width, height, method)),
 		Rgba8(ref p)  => Rgba8(sample::vertical_sample(p.as_slice(), height, width, height, method)),
+
+		Luma16(ref p)  => Luma16(sample::vertical_sample(p.as_slice(), height, width, height, method)),
+		LumaA16(ref p) => LumaA16(sample::vertical_sample(p.as_slice(), height, width, height, method)),
+		Rgb16(ref p)   => Rgb16(sample::vertical_sample(p.as_slice(), height, width, height, method)),
+		Rgba16(ref p)  => Rgba16(sample::vertical_sample(p.as_slice(), height, width, height, method)),
+
+		Bgr8(ref p)   => Bgr8(sample::vertical_sample(p.as_slice(), height, width, height, method)),
+		Bgra8(ref p)  => Bgra8(sample::vertical_sample(p.as_slice(), height, width, height, method)),
+
+		Rgb32F(ref p)  => Rgb32F(sample::vertical_sample(p.as_slice(), height, width, height, method)),
+		Rgba32F(ref p) => Rgba32F(sample::vertical_sample(p.as_slice(), height, width, height, method)),
+
+		Indexed8 { .. } => unreachable!(), // expanded above
 	};
 
 	let method = sample::Filter {
@@ -679,6 +1276,19 @@ pub fn blur(pixels:  &PixelBuf,
 		LumaA8(ref p) => LumaA8(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
 		Rgb8(ref p)   => Rgb8(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
 		Rgba8(ref p)  => Rgba8(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
+
+		Luma16(ref p)  => Luma16(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
+		LumaA16(ref p) => LumaA16(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
+		Rgb16(ref p)   => Rgb16(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
+		Rgba16(ref p)  => Rgba16(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
+
+		Bgr8(ref p)   => Bgr8(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
+		Bgra8(ref p)  => Bgra8(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
+
+		Rgb32F(ref p)  => Rgb32F(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
+		Rgba32F(ref p) => Rgba32F(sample::horizontal_sample(p.as_slice(), width, height, width, method)),
+
+		Indexed8 { .. } => unreachable!(), // expanded above
 	}
 }
 
@@ -688,8 +1298,8 @@ fn clamp<N: Num + PartialOrd>(a: N, min: N, max: N) -> N {
 	else { a }
 }
 
-fn subtract_pixels<A: Primitive, T: Pixel<A> + Clone>(pixels: &[T], blurred: &mut [T], threshold: i32) {
-	let max: A = Bounded::max_value();
+fn subtract_pixels<A: Primitive + ChannelMax, T: Pixel<A> + Clone>(pixels: &[T], blurred: &mut [T], threshold: i32) {
+	let max: A = ChannelMax::channel_max();
 
 	for (p, b) in pixels.iter().zip(blurred.mut_iter()) {
 		let a = p.map2(b.clone(), |c, d| {
@@ -739,6 +1349,30 @@ pub fn unsharpen(pixels:    &PixelBuf,
 			(&Rgba8(ref p), &Rgba8(ref mut b)) =>
 				subtract_pixels(p.as_slice(), b.as_mut_slice(), threshold),
 
+			(&Luma16(ref p), &Luma16(ref mut b)) =>
+				subtract_pixels(p.as_slice(), b.as_mut_slice(), threshold),
+
+			(&LumaA16(ref p), &LumaA16(ref mut b)) =>
+				subtract_pixels(p.as_slice(), b.as_mut_slice(), threshold),
+
+			(&Rgb16(ref p), &Rgb16(ref mut b)) =>
+				subtract_pixels(p.as_slice(), b.as_mut_slice(), threshold),
+
+			(&Rgba16(ref p), &Rgba16(ref mut b)) =>
+				subtract_pixels(p.as_slice(), b.as_mut_slice(), threshold),
+
+			(&Bgr8(ref p), &Bgr8(ref mut b)) =>
+				subtract_pixels(p.as_slice(), b.as_mut_slice(), threshold),
+
+			(&Bgra8(ref p), &Bgra8(ref mut b)) =>
+				subtract_pixels(p.as_slice(), b.as_mut_slice(), threshold),
+
+			(&Rgb32F(ref p), &Rgb32F(ref mut b)) =>
+				subtract_pixels(p.as_slice(), b.as_mut_slice(), threshold),
+
+			(&Rgba32F(ref p), &Rgba32F(ref mut b)) =>
+				subtract_pixels(p.as_slice(), b.as_mut_slice(), threshold),
+
 			(_, _) => fail!("blur operation returned different pixel types")
 		}
 	}
@@ -761,11 +1395,25 @@ pub fn filter3x3(pixels:  &PixelBuf,
 		LumaA8(ref p) => LumaA8(sample::filter_3x3(p.as_slice(), width, height, kernel)),
 		Rgb8(ref p)   => Rgb8(sample::filter_3x3(p.as_slice(), width, height, kernel)),
 		Rgba8(ref p)  => Rgba8(sample::filter_3x3(p.as_slice(), width, height, kernel)),
+
+		Luma16(ref p)  => Luma16(sample::filter_3x3(p.as_slice(), width, height, kernel)),
+		LumaA16(ref p) => LumaA16(sample::filter_3x3(p.as_slice(), width, height, kernel)),
+		Rgb16(ref p)   => Rgb16(sample::filter_3x3(p.as_slice(), width, height, kernel)),
+		Rgba16(ref p)  => Rgba16(sample::filter_3x3(p.as_slice(), width, height, kernel)),
+
+		Bgr8(ref p)   => Bgr8(sample::filter_3x3(p.as_slice(), width, height, kernel)),
+		Bgra8(ref p)  => Bgra8(sample::filter_3x3(p.as_slice(), width, height, kernel)),
+
+		Rgb32F(ref p)  => Rgb32F(sample::filter_3x3(p.as_slice(), width, height, kernel)),
+		Rgba32F(ref p) => Rgba32F(sample::filter_3x3(p.as_slice(), width, height, kernel)),
+
+		// Convolving palette indices is meaningless, so expand first.
+		Indexed8 { .. } => filter3x3(&pixels.expand_to_rgb(), width, height, kernel),
 	}
 }
 
-fn contrast<A: Primitive, T: Pixel<A>>(p: &[T], contrast: f32) -> Vec<T> {
-	let max: A = Bounded::max_value();
+fn contrast<A: Primitive + ChannelMax, T: Pixel<A>>(p: &[T], contrast: f32) -> Vec<T> {
+	let max: A = ChannelMax::channel_max();
 	let max = cast::<A, f32>(max).unwrap();
 
 	let percent = ((100.0 + contrast) / 100.0).powi(2);
@@ -785,11 +1433,25 @@ pub fn adjust_contrast(pixels: &PixelBuf, c: f32) -> PixelBuf {
 		LumaA8(ref p) => LumaA8(contrast(p.as_slice(), c)),
 		Rgb8(ref p)   => Rgb8(contrast(p.as_slice(), c)),
 		Rgba8(ref p)  => Rgba8(contrast(p.as_slice(), c)),
+
+		Luma16(ref p)  => Luma16(contrast(p.as_slice(), c)),
+		LumaA16(ref p) => LumaA16(contrast(p.as_slice(), c)),
+		Rgb16(ref p)   => Rgb16(contrast(p.as_slice(), c)),
+		Rgba16(ref p)  => Rgba16(contrast(p.as_slice(), c)),
+
+		Bgr8(ref p)   => Bgr8(contrast(p.as_slice(), c)),
+		Bgra8(ref p)  => Bgra8(contrast(p.as_slice(), c)),
+
+		Rgb32F(ref p)  => Rgb32F(contrast(p.as_slice(), c)),
+		Rgba32F(ref p) => Rgba32F(contrast(p.as_slice(), c)),
+
+		// Adjusting palette indices is meaningless, so expand first.
+		Indexed8 { .. } => adjust_contrast(&pixels.expand_to_rgb(), c),
 	}
 }
 
-fn bright<A: Primitive, T: Pixel<A>>(p: &[T], v: i32) -> Vec<T> {
-	let max: A = Bounded::max_value();
+fn bright<A: Primitive + ChannelMax, T: Pixel<A>>(p: &[T], v: i32) -> Vec<T> {
+	let max: A = ChannelMax::channel_max();
 	let max = cast::<A, i32>(max).unwrap();
 
 	p.iter().map(|a| a.map(|b| {
@@ -806,5 +1468,557 @@ pub fn brighten(pixels: &PixelBuf, c: i32) -> PixelBuf {
 		LumaA8(ref p) => LumaA8(bright(p.as_slice(), c)),
 		Rgb8(ref p)   => Rgb8(bright(p.as_slice(), c)),
 		Rgba8(ref p)  => Rgba8(bright(p.as_slice(), c)),
+
+		Luma16(ref p)  => Luma16(bright(p.as_slice(), c)),
+		LumaA16(ref p) => LumaA16(bright(p.as_slice(), c)),
+		Rgb16(ref p)   => Rgb16(bright(p.as_slice(), c)),
+		Rgba16(ref p)  => Rgba16(bright(p.as_slice(), c)),
+
+		Bgr8(ref p)   => Bgr8(bright(p.as_slice(), c)),
+		Bgra8(ref p)  => Bgra8(bright(p.as_slice(), c)),
+
+		Rgb32F(ref p)  => Rgb32F(bright(p.as_slice(), c)),
+		Rgba32F(ref p) => Rgba32F(bright(p.as_slice(), c)),
+
+		// Adjusting palette indices is meaningless, so expand first.
+		Indexed8 { .. } => brighten(&pixels.expand_to_rgb(), c),
+	}
+}
+
+/// Apply an affine transform to the four channels of ```p```.
+///
+/// ```order``` maps each ```channels4``` slot to the ```mult```/```add```
+/// index that drives it, so luma buffers can feed their value through index 0
+/// and their alpha through index 3 even though ```channels4``` packs alpha
+/// into the second slot.
+fn affine<A: Primitive + ChannelMax, T: Pixel<A>>(p:     &[T],
+	                             mult:  [f32, ..4],
+	                             add:   [i32, ..4],
+	                             order: [uint, ..4]) -> Vec<T> {
+	let max: A = ChannelMax::channel_max();
+	let maxf = cast::<A, f32>(max).unwrap();
+
+	p.iter().map(|pix| {
+		let (a, b, c, d) = pix.channels4();
+		let chans = [a, b, c, d];
+
+		let t = |slot: uint| -> A {
+			let i = order[slot];
+			let v = cast::<A, f32>(chans[slot]).unwrap() * mult[i] + add[i] as f32;
+			cast::<f32, A>(clamp(v, 0.0, maxf)).unwrap()
+		};
+
+		pix.from_channels(t(0), t(1), t(2), t(3))
+	}).collect()
+}
+
+/// Per-channel affine colour transform: ```channel' = clamp(channel * mult[i]
+/// + add[i], 0, max)``` applied independently to R, G, B and A.
+///
+/// This generalizes `brighten` (uniform, add-only) and `adjust_contrast` into
+/// a single pass that can tint, apply channel gains or fade alpha. Unlike
+/// `map`, alpha is transformable. Luma buffers drive their value from index 0
+/// and their alpha from index 3.
+pub fn apply_color_transform(pixels: &PixelBuf, mult: [f32, ..4], add: [i32, ..4]) -> PixelBuf {
+	// RGB-style buffers map slots straight through; luma buffers route alpha
+	// (packed in slot 1 by `channels4`) to index 3.
+	static RGB:  [uint, ..4] = [0, 1, 2, 3];
+	static LUMA: [uint, ..4] = [0, 3, 1, 2];
+
+	match *pixels {
+		Luma8(ref p)  => Luma8(affine(p.as_slice(), mult, add, LUMA)),
+		LumaA8(ref p) => LumaA8(affine(p.as_slice(), mult, add, LUMA)),
+		Rgb8(ref p)   => Rgb8(affine(p.as_slice(), mult, add, RGB)),
+		Rgba8(ref p)  => Rgba8(affine(p.as_slice(), mult, add, RGB)),
+
+		Luma16(ref p)  => Luma16(affine(p.as_slice(), mult, add, LUMA)),
+		LumaA16(ref p) => LumaA16(affine(p.as_slice(), mult, add, LUMA)),
+		Rgb16(ref p)   => Rgb16(affine(p.as_slice(), mult, add, RGB)),
+		Rgba16(ref p)  => Rgba16(affine(p.as_slice(), mult, add, RGB)),
+
+		Bgr8(ref p)   => Bgr8(affine(p.as_slice(), mult, add, RGB)),
+		Bgra8(ref p)  => Bgra8(affine(p.as_slice(), mult, add, RGB)),
+
+		Rgb32F(ref p)  => Rgb32F(affine(p.as_slice(), mult, add, RGB)),
+		Rgba32F(ref p) => Rgba32F(affine(p.as_slice(), mult, add, RGB)),
+
+		// Transforming palette indices is meaningless, so expand first.
+		Indexed8 { .. } => apply_color_transform(&pixels.expand_to_rgb(), mult, add),
+	}
+}
+
+/// The smoothstep fade curve ```6t^5 - 15t^4 + 10t^3``` used by Perlin noise
+/// to ease the interpolation weights at lattice boundaries.
+fn fade(t: f32) -> f32 {
+	t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+	a + t * (b - a)
+}
+
+/// A seedable permutation and gradient table driving `turbulence`.
+struct NoiseLattice {
+	perm: [uint, ..512],
+	grad: [(f32, f32), ..256],
+}
+
+impl NoiseLattice {
+	/// Build the lattice by shuffling the identity permutation with a small
+	/// linear-congruential generator seeded from ```seed```, then deriving a
+	/// unit gradient vector for each entry from its shuffled value.
+	fn new(seed: u32) -> NoiseLattice {
+		let mut state = if seed == 0 { 0x9e3779b9 } else { seed };
+		let mut next = || {
+			// Numerical Recipes LCG; only the high bits are used.
+			state = state * 1664525 + 1013904223;
+			state
+		};
+
+		let mut perm = [0u, ..512];
+		for i in range(0u, 256) {
+			perm[i] = i;
+		}
+
+		// Fisher-Yates using the seeded generator.
+		let mut i = 255u;
+		while i > 0 {
+			let j = ((next() >> 16) as uint) % (i + 1);
+			let t = perm[i];
+			perm[i] = perm[j];
+			perm[j] = t;
+			i -= 1;
+		}
+
+		// Mirror the table so corner lookups can index without masking twice.
+		for i in range(0u, 256) {
+			perm[256 + i] = perm[i];
+		}
+
+		let mut grad = [(0.0f32, 0.0f32), ..256];
+		for i in range(0u, 256) {
+			let a = (perm[i] as f32) / 256.0 * 6.283185307;
+			grad[i] = (a.cos(), a.sin());
+		}
+
+		NoiseLattice { perm: perm, grad: grad }
+	}
+
+	/// Gradient noise at ```(x, y)``` for the lattice cell the point falls in.
+	/// When ```period``` is non-zero the integer lattice is wrapped modulo it,
+	/// producing a seamlessly tileable result.
+	fn noise(&self, x: f32, y: f32, period: uint) -> f32 {
+		let wrap = |v: int| -> uint {
+			if period == 0 {
+				(v as uint) & 255
+			} else {
+				(((v % period as int) + period as int) % period as int) as uint
+			}
+		};
+
+		let x0 = x.floor();
+		let y0 = y.floor();
+		let fx = x - x0;
+		let fy = y - y0;
+
+		let ix = wrap(x0 as int);
+		let iy = wrap(y0 as int);
+		let ix1 = wrap(x0 as int + 1);
+		let iy1 = wrap(y0 as int + 1);
+
+		let corner = |gx: uint, gy: uint, dx: f32, dy: f32| -> f32 {
+			//The tile coordinates are already period-wrapped; mask the permutation
+			//lookups to keep them inside the 512-entry table when period > 256.
+			let (ax, ay) = self.grad[self.perm[self.perm[gx & 255] + (gy & 255)]];
+			ax * dx + ay * dy
+		};
+
+		let n00 = corner(ix,  iy,  fx,       fy);
+		let n10 = corner(ix1, iy,  fx - 1.0, fy);
+		let n01 = corner(ix,  iy1, fx,       fy - 1.0);
+		let n11 = corner(ix1, iy1, fx - 1.0, fy - 1.0);
+
+		let u = fade(fx);
+		let v = fade(fy);
+
+		lerp(v, lerp(u, n00, n10), lerp(u, n01, n11))
+	}
+}
+
+/// Synthesize a procedural noise image, useful for clouds, textures and
+/// dissolve effects.
+///
+/// Classic gradient (Perlin) noise is summed over ```num_octaves``` octaves,
+/// each doubling ```base_freq_x```/```base_freq_y``` and halving the
+/// amplitude. When ```stitch``` is set each octave's lattice is wrapped every
+/// ```round(width * frequency)``` cells on both axes; the result tiles
+/// seamlessly only when ```width * base_freq_x``` is a whole number and
+/// ```height * base_freq_y``` equals it. The signed octaves produce smooth "fractal" noise; taking
+/// the absolute value of each octave first yields the billowy "turbulence"
+/// look — this function uses the turbulence form. Each channel is generated
+/// independently by offsetting the lattice, and the summed amplitude is
+/// normalized so the output stays within ```[0, 255]```.
+pub fn turbulence(width:        u32,
+	          height:       u32,
+	          base_freq_x:  f32,
+	          base_freq_y:  f32,
+	          num_octaves:  uint,
+	          seed:         u32,
+	          stitch:       bool) -> PixelBuf {
+
+	let lattice = NoiseLattice::new(seed);
+
+	// Per-channel lattice offsets keep the R/G/B/A planes decorrelated.
+	static OFFSETS: [f32, ..4] = [0.0, 37.0, 101.0, 211.0];
+
+	// Amplitudes halve each octave, so the maximum possible sum is
+	// 1 + 1/2 + 1/4 + ... which we use to normalize back into range.
+	let mut max_amp = 0.0f32;
+	{
+		let mut a = 1.0f32;
+		for _ in range(0u, num_octaves) {
+			max_amp += a;
+			a *= 0.5;
+		}
+	}
+	if max_amp == 0.0 {
+		max_amp = 1.0;
+	}
+
+	let mut pixels = Vec::with_capacity((width * height) as uint);
+
+	for y in range(0u32, height) {
+		for x in range(0u32, width) {
+			let mut channels = [0u8, ..4];
+
+			for c in range(0u, 4) {
+				let mut sum  = 0.0f32;
+				let mut amp  = 1.0f32;
+				let mut fx   = base_freq_x;
+				let mut fy   = base_freq_y;
+
+				for _ in range(0u, num_octaves) {
+					let sx = x as f32 * fx + OFFSETS[c];
+					let sy = y as f32 * fy + OFFSETS[c];
+
+					let tile = if stitch {
+						// Round the octave period to an integer cell count so
+						// opposite edges land on the same lattice nodes.
+						let p = (width as f32 * fx).round() as uint;
+						if p == 0 { 1 } else { p }
+					} else {
+						0
+					};
+
+					sum += lattice.noise(sx, sy, tile).abs() * amp;
+
+					amp *= 0.5;
+					fx  *= 2.0;
+					fy  *= 2.0;
+				}
+
+				let v = (sum / max_amp) * 255.0;
+				channels[c] = clamp(v, 0.0, 255.0) as u8;
+			}
+
+			pixels.push(Rgba::<u8>(channels[0], channels[1], channels[2], channels[3]));
+		}
+	}
+
+	Rgba8(pixels)
+}
+/// The ways `blend` can combine the top layer's colour with the bottom.
+///
+/// `Over` is a plain Porter-Duff source-over composite; the remaining modes
+/// are the separable blends that replace the top colour term before the
+/// over-composite is applied.
+#[deriving(PartialEq, Clone, Show, Copy)]
+pub enum BlendMode {
+	Over,
+	Multiply,
+	Screen,
+	Overlay,
+	Darken,
+	Lighten,
+}
+
+/// Convert any ```PixelBuf``` into a flat ```Rgba<u8>``` vector so the blend
+/// math always has four 8-bit channels and an alpha to work with.
+fn to_rgba8_vec(buf: &PixelBuf) -> Vec<Rgba<u8>> {
+	fn from_u16(p: Rgba<u16>) -> Rgba<u8> {
+		let (r, g, b, a) = p.channels();
+		Rgba::<u8>((r >> 8) as u8, (g >> 8) as u8, (b >> 8) as u8, (a >> 8) as u8)
+	}
+
+	fn from_f32(p: Rgba<f32>) -> Rgba<u8> {
+		let (r, g, b, a) = p.channels();
+		let q = |c: f32| clamp(c * 255.0, 0.0, 255.0) as u8;
+		Rgba::<u8>(q(r), q(g), q(b), q(a))
+	}
+
+	match *buf {
+		Rgba8(ref p) => p.clone(),
+
+		Luma8(ref p)  => p.iter().map(|x| x.to_rgba()).collect(),
+		LumaA8(ref p) => p.iter().map(|x| x.to_rgba()).collect(),
+		Rgb8(ref p)   => p.iter().map(|x| x.to_rgba()).collect(),
+		Bgr8(ref p)   => p.iter().map(|x| x.to_rgba()).collect(),
+		Bgra8(ref p)  => p.iter().map(|x| x.to_rgba()).collect(),
+
+		Luma16(ref p)  => p.iter().map(|x| from_u16(x.to_rgba())).collect(),
+		LumaA16(ref p) => p.iter().map(|x| from_u16(x.to_rgba())).collect(),
+		Rgb16(ref p)   => p.iter().map(|x| from_u16(x.to_rgba())).collect(),
+		Rgba16(ref p)  => p.iter().map(|x| from_u16(x.to_rgba())).collect(),
+
+		Rgb32F(ref p)  => p.iter().map(|x| from_f32(x.to_rgba())).collect(),
+		Rgba32F(ref p) => p.iter().map(|x| from_f32(x.to_rgba())).collect(),
+
+		Indexed8 { .. } => to_rgba8_vec(&buf.expand_to_rgb()),
 	}
-}
\ No newline at end of file
+}
+
+/// Apply a separable blend mode to a single pair of normalized channels,
+/// where ```b``` is the backdrop (bottom) and ```s``` the source (top).
+fn blend_channel(mode: BlendMode, b: f32, s: f32) -> f32 {
+	match mode {
+		Over     => s,
+		Multiply => b * s,
+		Screen   => b + s - b * s,
+		Darken   => if b < s { b } else { s },
+		Lighten  => if b > s { b } else { s },
+		Overlay  => if b < 0.5 { 2.0 * b * s } else { 1.0 - 2.0 * (1.0 - b) * (1.0 - s) },
+	}
+}
+
+/// Composite ```top``` over ```bottom```, applying ```mode``` to the colour
+/// channels before the Porter-Duff source-over step.
+///
+/// The two buffers must have the same length; both are converted to
+/// ```Rgba8``` first so the mode math always has an alpha. All arithmetic is
+/// carried out in normalized ```[0, 1]``` space and requantized to 8 bits.
+pub fn blend(bottom: &PixelBuf, top: &PixelBuf, mode: BlendMode) -> PixelBuf {
+	let b = to_rgba8_vec(bottom);
+	let t = to_rgba8_vec(top);
+
+	let out = b.iter().zip(t.iter()).map(|(bp, tp)| {
+		let (br, bg, bb, ba) = bp.channels();
+		let (tr, tg, tb, ta) = tp.channels();
+
+		let n = |c: u8| c as f32 / 255.0;
+		let (br, bg, bb, ba) = (n(br), n(bg), n(bb), n(ba));
+		let (tr, tg, tb, ta) = (n(tr), n(tg), n(tb), n(ta));
+
+		let out_a = ta + ba * (1.0 - ta);
+
+		let channel = |bc: f32, tc: f32| -> u8 {
+			// The mode replaces the straight top colour term, then the result
+			// is composited over the backdrop.
+			let s = blend_channel(mode, bc, tc);
+			let o = s + bc * (1.0 - ta);
+			clamp(o * 255.0, 0.0, 255.0) as u8
+		};
+
+		Rgba::<u8>(channel(br, tr),
+		           channel(bg, tg),
+		           channel(bb, tb),
+		           clamp(out_a * 255.0, 0.0, 255.0) as u8)
+	}).collect();
+
+	Rgba8(out)
+}
+
+/// The source samples that contribute to a single destination coordinate,
+/// together with their normalized filter weights.
+struct Contribution {
+	left:    uint,
+	weights: Vec<f32>,
+}
+
+/// Evaluate the selected filter kernel at ```x```.
+fn kernel_weight(filter: sample::FilterType, x: f32) -> f32 {
+	match filter {
+		sample::Nearest    => sample::box_kernel(x),
+		sample::Triangle   => sample::triangle_kernel(x),
+		sample::CatmullRom => sample::catmullrom_kernel(x),
+		sample::Gaussian   => sample::gaussian_kernel(x),
+		sample::Lanczos3   => sample::lanczos3_kernel(x),
+	}
+}
+
+fn filter_support(filter: sample::FilterType) -> f32 {
+	match filter {
+		sample::Nearest    => 0.5,
+		sample::Triangle   => 1.0,
+		sample::CatmullRom => 2.0,
+		sample::Gaussian   => 3.0,
+		sample::Lanczos3   => 3.0,
+	}
+}
+
+/// Precompute the contribution table mapping each of ```dst``` destination
+/// coordinates to its source window and normalized weights, for resizing an
+/// axis of length ```src``` with the given ```filter```.
+fn build_contributions(src: u32, dst: u32, filter: sample::FilterType) -> Vec<Contribution> {
+	let support = filter_support(filter);
+
+	let ratio   = src as f32 / dst as f32;
+	// When downscaling the kernel has to straddle more source samples.
+	let sratio  = if ratio < 1.0 { 1.0 } else { ratio };
+	let src_sup = support * sratio;
+
+	let mut table = Vec::with_capacity(dst as uint);
+
+	for outx in range(0u32, dst) {
+		let center = (outx as f32 + 0.5) * ratio;
+
+		let left  = clamp((center - src_sup).floor() as i32, 0, src as i32 - 1) as uint;
+		let right = clamp((center + src_sup).ceil()  as i32, 1, src as i32) as uint;
+
+		let mut weights = Vec::with_capacity(right - left);
+		let mut sum = 0.0f32;
+		for i in range(left, right) {
+			let w = kernel_weight(filter, (i as f32 + 0.5 - center) / sratio);
+			weights.push(w);
+			sum += w;
+		}
+
+		// Normalize so the weights preserve overall brightness.
+		if sum != 0.0 {
+			for w in weights.mut_iter() {
+				*w /= sum;
+			}
+		}
+
+		table.push(Contribution { left: left, weights: weights });
+	}
+
+	table
+}
+
+/// Apply a horizontal contribution table to a buffer, producing a buffer whose
+/// width is ```table.len()``` and whose height is unchanged.
+fn apply_horizontal<A: Primitive + ChannelMax, T: Pixel<A> + Clone>(src:    &[T],
+	                                               width:  u32,
+	                                               height: u32,
+	                                               table:  &[Contribution]) -> Vec<T> {
+	let max: A = ChannelMax::channel_max();
+	let maxf = cast::<A, f32>(max).unwrap();
+	let nwidth = table.len() as u32;
+
+	let mut out = Vec::with_capacity((nwidth * height) as uint);
+
+	for y in range(0u32, height) {
+		let row = (y * width) as uint;
+		for c in table.iter() {
+			let (mut a, mut b, mut cc, mut d) = (0.0f32, 0.0f32, 0.0f32, 0.0f32);
+			for (i, &w) in c.weights.iter().enumerate() {
+				let (pa, pb, pc, pd) = src[row + c.left + i].channels4();
+				a  += cast::<A, f32>(pa).unwrap() * w;
+				b  += cast::<A, f32>(pb).unwrap() * w;
+				cc += cast::<A, f32>(pc).unwrap() * w;
+				d  += cast::<A, f32>(pd).unwrap() * w;
+			}
+			let q = |v: f32| cast::<f32, A>(clamp(v, 0.0, maxf)).unwrap();
+			out.push(src[row + c.left].from_channels(q(a), q(b), q(cc), q(d)));
+		}
+	}
+
+	out
+}
+
+/// Apply a vertical contribution table to a buffer, producing a buffer whose
+/// height is ```table.len()``` and whose width is unchanged.
+fn apply_vertical<A: Primitive + ChannelMax, T: Pixel<A> + Clone>(src:    &[T],
+	                                             width:  u32,
+	                                             height: u32,
+	                                             table:  &[Contribution]) -> Vec<T> {
+	let max: A = ChannelMax::channel_max();
+	let maxf = cast::<A, f32>(max).unwrap();
+	let nheight = table.len() as u32;
+
+	let mut out = Vec::with_capacity((width * nheight) as uint);
+
+	for c in table.iter() {
+		for x in range(0u32, width) {
+			let (mut a, mut b, mut cc, mut d) = (0.0f32, 0.0f32, 0.0f32, 0.0f32);
+			for (i, &w) in c.weights.iter().enumerate() {
+				let idx = ((c.left + i) as u32 * width + x) as uint;
+				let (pa, pb, pc, pd) = src[idx].channels4();
+				a  += cast::<A, f32>(pa).unwrap() * w;
+				b  += cast::<A, f32>(pb).unwrap() * w;
+				cc += cast::<A, f32>(pc).unwrap() * w;
+				d  += cast::<A, f32>(pd).unwrap() * w;
+			}
+			let q = |v: f32| cast::<f32, A>(clamp(v, 0.0, maxf)).unwrap();
+			let base = (c.left as u32 * width + x) as uint;
+			out.push(src[base].from_channels(q(a), q(b), q(cc), q(d)));
+		}
+	}
+
+	out
+}
+
+/// A resizer that caches the per-row and per-column filter weights for a fixed
+/// ```(width, height) -> (nwidth, nheight)``` transform.
+///
+/// Building a `Resizer` computes the contribution tables once; `resize` then
+/// reuses them, so resizing a batch of equally-sized frames — thumbnail grids,
+/// video frames — pays the filter-setup cost a single time instead of on every
+/// call like the standalone `resize` function does.
+pub struct Resizer {
+	width:   u32,
+	height:  u32,
+	nwidth:  u32,
+	columns: Vec<Contribution>,
+	rows:    Vec<Contribution>,
+}
+
+impl Resizer {
+	/// Precompute the horizontal (column) and vertical (row) weight tables for
+	/// resizing a ```width``` x ```height``` buffer to ```nwidth``` x
+	/// ```nheight``` with ```filter```.
+	pub fn new(width:   u32,
+	           height:  u32,
+	           nwidth:  u32,
+	           nheight: u32,
+	           filter:  sample::FilterType) -> Resizer {
+		Resizer {
+			width:   width,
+			height:  height,
+			nwidth:  nwidth,
+			columns: build_contributions(width, nwidth, filter),
+			rows:    build_contributions(height, nheight, filter),
+		}
+	}
+
+	/// Scale a single pixel plane width-first then height, using the cached
+	/// weight tables.
+	fn run<A: Primitive + ChannelMax, T: Pixel<A> + Clone>(&self, p: &[T]) -> Vec<T> {
+		let h = apply_horizontal(p, self.width, self.height, self.columns.as_slice());
+		apply_vertical(h.as_slice(), self.nwidth, self.height, self.rows.as_slice())
+	}
+
+	/// Resize ```src``` using the cached weight tables, scaling width first and
+	/// then height. Indexed buffers are expanded to direct colour first.
+	pub fn resize(&self, src: &PixelBuf) -> PixelBuf {
+		match *src {
+			Luma8(ref p)  => Luma8(self.run(p.as_slice())),
+			LumaA8(ref p) => LumaA8(self.run(p.as_slice())),
+			Rgb8(ref p)   => Rgb8(self.run(p.as_slice())),
+			Rgba8(ref p)  => Rgba8(self.run(p.as_slice())),
+
+			Luma16(ref p)  => Luma16(self.run(p.as_slice())),
+			LumaA16(ref p) => LumaA16(self.run(p.as_slice())),
+			Rgb16(ref p)   => Rgb16(self.run(p.as_slice())),
+			Rgba16(ref p)  => Rgba16(self.run(p.as_slice())),
+
+			Bgr8(ref p)   => Bgr8(self.run(p.as_slice())),
+			Bgra8(ref p)  => Bgra8(self.run(p.as_slice())),
+
+			Rgb32F(ref p)  => Rgb32F(self.run(p.as_slice())),
+			Rgba32F(ref p) => Rgba32F(self.run(p.as_slice())),
+
+			Indexed8 { .. } => self.resize(&src.expand_to_rgb()),
+		}
+	}
+}
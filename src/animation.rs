@@ -0,0 +1,227 @@
+use color;
+use color::Pixel;
+use image::{
+    ImageBuf,
+    GenericImage,
+    ImageResult,
+};
+
+/// The disposal method controls what happens to the canvas once a frame has
+/// been shown, before the next frame is composited.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum DisposalMethod {
+    /// Leave the frame in place; the next frame draws on top of it.
+    Keep,
+
+    /// Clear the frame's rectangle back to the background before the next frame.
+    RestoreBackground,
+
+    /// Restore the canvas to its state before this frame was drawn.
+    RestorePrevious,
+}
+
+/// The delay before the next frame, stored as a rational number of seconds to
+/// avoid rounding the fractional centisecond delays GIF uses.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct Delay {
+    numer: u32,
+    denom: u32,
+}
+
+impl Delay {
+    /// Construct a delay of ```numer```/```denom``` seconds.
+    pub fn new(numer: u32, denom: u32) -> Delay {
+        Delay {numer: numer, denom: denom}
+    }
+
+    /// Construct a delay from a whole number of milliseconds.
+    pub fn from_millis(ms: u32) -> Delay {
+        Delay {numer: ms, denom: 1000}
+    }
+
+    /// The delay rounded down to whole milliseconds.
+    pub fn to_millis(&self) -> u32 {
+        if self.denom == 0 {
+            0
+        } else {
+            self.numer * 1000 / self.denom
+        }
+    }
+}
+
+/// A single frame of an animation.
+///
+/// Decoders yield *partial* frames: an ```ImageBuf``` covering only the changed
+/// rectangle, positioned at ```(left, top)```, plus the timing and disposal hint
+/// needed to composite it against the running canvas.
+#[deriving(Clone)]
+pub struct Frame {
+    buffer: ImageBuf<color::Rgba<u8>>,
+    left: u32,
+    top: u32,
+    delay: Delay,
+    disposal: DisposalMethod,
+}
+
+impl Frame {
+    /// Construct a new frame.
+    pub fn new(buffer: ImageBuf<color::Rgba<u8>>,
+               left: u32,
+               top: u32,
+               delay: Delay,
+               disposal: DisposalMethod) -> Frame {
+        Frame {
+            buffer: buffer,
+            left: left,
+            top: top,
+            delay: delay,
+            disposal: disposal,
+        }
+    }
+
+    /// This frame's pixel buffer.
+    pub fn buffer<'a>(&'a self) -> &'a ImageBuf<color::Rgba<u8>> {
+        &self.buffer
+    }
+
+    /// The frame's top-left offset on the canvas.
+    pub fn offset(&self) -> (u32, u32) {
+        (self.left, self.top)
+    }
+
+    /// The delay before the following frame.
+    pub fn delay(&self) -> Delay {
+        self.delay.clone()
+    }
+
+    /// This frame's disposal method.
+    pub fn disposal(&self) -> DisposalMethod {
+        self.disposal
+    }
+}
+
+/// An iterator over the frames of an animation.
+pub struct Frames {
+    frames: Vec<Frame>,
+    pos: uint,
+}
+
+impl Frames {
+    /// Construct a ```Frames``` from an already-decoded set of partial frames.
+    pub fn new(frames: Vec<Frame>) -> Frames {
+        Frames {frames: frames, pos: 0}
+    }
+
+    /// Composite these partial frames against a ```width``` x ```height```
+    /// canvas, honouring each frame's offset and disposal method, and return a
+    /// new ```Frames``` of fully-rendered, canvas-sized frames ready to display.
+    pub fn composite(self, width: u32, height: u32) -> Frames {
+        let transparent: color::Rgba<u8> = color::Rgba(0, 0, 0, 0);
+        let mut canvas = ImageBuf::from_pixel(width, height, transparent);
+
+        let mut out = Vec::with_capacity(self.frames.len());
+
+        for frame in self.frames.move_iter() {
+            //Snapshot the canvas first when we may need to roll back to it.
+            let snapshot = match frame.disposal {
+                RestorePrevious => Some(canvas.clone()),
+                _               => None,
+            };
+
+            blit(&mut canvas, &frame.buffer, frame.left, frame.top);
+
+            out.push(Frame::new(canvas.clone(), 0, 0, frame.delay.clone(), Keep));
+
+            match frame.disposal {
+                Keep              => {}
+                RestoreBackground => clear_rect(&mut canvas, &frame, transparent),
+                RestorePrevious   => canvas = snapshot.unwrap(),
+            }
+        }
+
+        Frames::new(out)
+    }
+}
+
+impl Iterator<Frame> for Frames {
+    fn next(&mut self) -> Option<Frame> {
+        if self.pos >= self.frames.len() {
+            None
+        } else {
+            let f = self.frames.get(self.pos).clone();
+            self.pos += 1;
+            Some(f)
+        }
+    }
+}
+
+/// The trait animation decoders implement to expose their frames.
+pub trait AnimationDecoder {
+    /// Consume the decoder and return its partial frames. Callers that want
+    /// ready-to-display images should run the result through
+    /// ```Frames::composite```.
+    fn into_frames(self) -> ImageResult<Frames>;
+}
+
+//Alpha-composite `src` onto `dst` with its top-left corner at (x, y).
+fn blit(dst: &mut ImageBuf<color::Rgba<u8>>,
+        src: &ImageBuf<color::Rgba<u8>>,
+        x: u32,
+        y: u32) {
+
+    let (dw, dh) = dst.dimensions();
+    let (sw, sh) = src.dimensions();
+
+    for j in range(0, sh) {
+        for i in range(0, sw) {
+            let (px, py) = (x + i, y + j);
+            if px >= dw || py >= dh {
+                continue
+            }
+
+            let s = src.get_pixel(i, j);
+            let d = dst.get_pixel(px, py);
+
+            dst.put_pixel(px, py, over(s, d));
+        }
+    }
+}
+
+//Porter-Duff source-over of two straight-alpha RGBA pixels.
+fn over(src: color::Rgba<u8>, dst: color::Rgba<u8>) -> color::Rgba<u8> {
+    let (sr, sg, sb, sa) = src.channels();
+    let (dr, dg, db, da) = dst.channels();
+
+    let sa = sa as u32;
+    let inv = 255 - sa;
+
+    let oa = sa + da as u32 * inv / 255;
+
+    if oa == 0 {
+        return color::Rgba(0, 0, 0, 0)
+    }
+
+    let blend = |s: u8, d: u8| {
+        ((s as u32 * sa + d as u32 * da as u32 * inv / 255) / oa) as u8
+    };
+
+    color::Rgba(blend(sr, dr), blend(sg, dg), blend(sb, db), oa as u8)
+}
+
+//Reset the frame's rectangle on the canvas to the background pixel.
+fn clear_rect(canvas: &mut ImageBuf<color::Rgba<u8>>,
+              frame: &Frame,
+              background: color::Rgba<u8>) {
+
+    let (cw, ch) = canvas.dimensions();
+    let (fw, fh) = frame.buffer.dimensions();
+
+    for j in range(0, fh) {
+        for i in range(0, fw) {
+            let (px, py) = (frame.left + i, frame.top + j);
+            if px < cw && py < ch {
+                canvas.put_pixel(px, py, background);
+            }
+        }
+    }
+}
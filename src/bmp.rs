@@ -0,0 +1,108 @@
+use std::io;
+
+use color;
+use image;
+use image::ImageEncoder;
+use image::ImageResult;
+
+/// A BMP encoder.
+///
+/// Writes an uncompressed Windows bitmap: a 14-byte `BITMAPFILEHEADER`
+/// followed by a 40-byte `BITMAPINFOHEADER`. Pixel rows are stored bottom-up
+/// and each row is padded to a four-byte boundary, as the format requires.
+pub struct BMPEncoder<W> {
+    w: W,
+}
+
+impl<W: Writer> BMPEncoder<W> {
+    /// Create a new encoder that writes its output to ```w```.
+    pub fn new(w: W) -> BMPEncoder<W> {
+        BMPEncoder { w: w }
+    }
+
+    /// Encode the image ```buf``` and write it to the wrapped writer.
+    pub fn encode(&mut self,
+                  buf:    &[u8],
+                  width:  u32,
+                  height: u32,
+                  color:  color::ColorType) -> io::IoResult<()> {
+
+        // BMP stores three or four bytes per pixel in BGR(A) order; any other
+        // color type is rejected with `InvalidInput`.
+        let (channels, bpp) = match color {
+            color::RGB(8)  => (3u, 24u16),
+            color::RGBA(8) => (4u, 32u16),
+            color::BGR(8)  => (3u, 24u16),
+            color::BGRA(8) => (4u, 32u16),
+            _              => return Err(io::standard_error(io::InvalidInput)),
+        };
+
+        // Rows are padded up to the next multiple of four bytes.
+        let row_bytes = width as uint * channels;
+        let padding   = (4 - row_bytes % 4) % 4;
+        let stride    = row_bytes + padding;
+
+        let image_size = stride * height as uint;
+        let offset     = 14 + 40;
+        let file_size  = offset + image_size;
+
+        // BITMAPFILEHEADER
+        try!(self.w.write(bytes!("BM")));
+        try!(self.w.write_le_u32(file_size as u32));
+        try!(self.w.write_le_u16(0));            // reserved
+        try!(self.w.write_le_u16(0));            // reserved
+        try!(self.w.write_le_u32(offset as u32));
+
+        // BITMAPINFOHEADER
+        try!(self.w.write_le_u32(40));           // header size
+        try!(self.w.write_le_i32(width as i32));
+        try!(self.w.write_le_i32(height as i32));
+        try!(self.w.write_le_u16(1));            // planes
+        try!(self.w.write_le_u16(bpp));
+        try!(self.w.write_le_u32(0));            // BI_RGB, no compression
+        try!(self.w.write_le_u32(image_size as u32));
+        try!(self.w.write_le_i32(0));            // x pixels per metre
+        try!(self.w.write_le_i32(0));            // y pixels per metre
+        try!(self.w.write_le_u32(0));            // colors used
+        try!(self.w.write_le_u32(0));            // important colors
+
+        let pad = [0u8, ..3];
+
+        // Rows are emitted bottom-up, each swizzled to BGR(A) order.
+        for y in range(0, height).rev() {
+            let start = y as uint * row_bytes;
+            let row   = buf.slice(start, start + row_bytes);
+
+            for px in row.chunks(channels) {
+                match color {
+                    color::RGB(8) | color::RGBA(8) => {
+                        try!(self.w.write_u8(px[2]));
+                        try!(self.w.write_u8(px[1]));
+                        try!(self.w.write_u8(px[0]));
+                        if channels == 4 {
+                            try!(self.w.write_u8(px[3]));
+                        }
+                    }
+
+                    // BGR(A) data is already in the byte order BMP wants.
+                    _ => try!(self.w.write(px)),
+                }
+            }
+
+            try!(self.w.write(pad.slice_to(padding)));
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Writer> ImageEncoder for BMPEncoder<W> {
+    fn write_image(self, buf: &[u8], width: u32, height: u32, color: color::ColorType) -> ImageResult<()> {
+        let mut e = self;
+
+        match e.encode(buf, width, height, color) {
+            Ok(())  => Ok(()),
+            Err(_)  => Err(image::IoError),
+        }
+    }
+}
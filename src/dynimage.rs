@@ -1,21 +1,25 @@
 use std::io;
-use std::ascii::StrAsciiExt;
 
 use ppm;
 use gif;
 use webp;
 use jpeg;
 use png;
+use bmp;
+use tiff;
 
 use color;
 use imageops;
 use image;
 use image:: {
+    AsBytes,
     ImageBuf,
     GenericImage,
     ImageDecoder,
+    ImageEncoder,
     ImageResult,
     ImageFormat,
+    ImageOutputFormat,
 };
 
 ///A Dynamic Image
@@ -32,6 +36,12 @@ pub enum DynamicImage {
 
     /// Each pixel in this image is 8-bit Rgb with alpha
     ImageRgba8(ImageBuf<color::Rgba<u8>>),
+
+    /// Each pixel in this image is 8-bit Bgr, i.e. Rgb in native framebuffer order
+    ImageBgr8(ImageBuf<color::Bgr<u8>>),
+
+    /// Each pixel in this image is 8-bit Bgr with alpha
+    ImageBgra8(ImageBuf<color::Bgra<u8>>),
 }
 
 macro_rules! dynamic_map(
@@ -41,6 +51,8 @@ macro_rules! dynamic_map(
                         ImageLumaA8(ref $image) => ImageLumaA8($action),
                         ImageRgb8(ref $image) => ImageRgb8($action),
                         ImageRgba8(ref $image) => ImageRgba8($action),
+                        ImageBgr8(ref $image) => ImageBgr8($action),
+                        ImageBgra8(ref $image) => ImageBgra8($action),
                 }
         );
 
@@ -50,6 +62,8 @@ macro_rules! dynamic_map(
                         ImageLumaA8(ref mut $image) => ImageLumaA8($action),
                         ImageRgb8(ref mut $image) => ImageRgb8($action),
                         ImageRgba8(ref mut $image) => ImageRgba8($action),
+                        ImageBgr8(ref mut $image) => ImageBgr8($action),
+                        ImageBgra8(ref mut $image) => ImageBgra8($action),
                 }
         );
 
@@ -59,6 +73,8 @@ macro_rules! dynamic_map(
                         ImageLumaA8(ref $image) => $action,
                         ImageRgb8(ref $image) => $action,
                         ImageRgba8(ref $image) => $action,
+                        ImageBgr8(ref $image) => $action,
+                        ImageBgra8(ref $image) => $action,
                 }
         );
 
@@ -68,6 +84,8 @@ macro_rules! dynamic_map(
                         ImageLumaA8(ref mut $image) => $action,
                         ImageRgb8(ref mut $image) => $action,
                         ImageRgba8(ref mut $image) => $action,
+                        ImageBgr8(ref mut $image) => $action,
+                        ImageBgra8(ref mut $image) => $action,
                 }
         );
 )
@@ -105,6 +123,38 @@ impl DynamicImage {
         }
     }
 
+    ///Return a reference to an 8bit BGR image
+    pub fn as_bgr8 < 'a>(&'a self) -> Option<&'a ImageBuf<color::Bgr<u8>>> {
+        match *self {
+            ImageBgr8(ref p) => Some(p),
+            _                => None
+        }
+    }
+
+    ///Return a mutable reference to an 8bit BGR image
+    pub fn as_mut_bgr8<'a>(&'a mut self) -> Option<&'a mut ImageBuf<color::Bgr<u8>>> {
+        match *self {
+            ImageBgr8(ref mut p) => Some(p),
+            _                    => None
+        }
+    }
+
+    ///Return a reference to an 8bit BGRA image
+    pub fn as_bgra8 < 'a>(&'a self) -> Option<&'a ImageBuf<color::Bgra<u8>>> {
+        match *self {
+            ImageBgra8(ref p) => Some(p),
+            _                 => None
+        }
+    }
+
+    ///Return a mutable reference to an 8bit BGRA image
+    pub fn as_mut_bgra8<'a>(&'a mut self) -> Option<&'a mut ImageBuf<color::Bgra<u8>>> {
+        match *self {
+            ImageBgra8(ref mut p) => Some(p),
+            _                     => None
+        }
+    }
+
     ///Return a reference to an 8bit Grayscale image
     pub fn as_luma8 < 'a>(&'a self) -> Option<&'a ImageBuf<color::Luma<u8>>> {
         match *self {
@@ -154,6 +204,8 @@ impl DynamicImage {
             ImageLumaA8(_) => color::GreyA(8),
             ImageRgb8(_) => color::RGB(8),
             ImageRgba8(_) => color::RGBA(8),
+            ImageBgr8(_) => color::BGR(8),
+            ImageBgra8(_) => color::BGRA(8),
         }
     }
 
@@ -164,6 +216,8 @@ impl DynamicImage {
             ImageLumaA8(ref p) => ImageLuma8(imageops::grayscale(p)),
             ImageRgb8(ref p) => ImageLuma8(imageops::grayscale(p)),
             ImageRgba8(ref p) => ImageLuma8(imageops::grayscale(p)),
+            ImageBgr8(ref p) => ImageLuma8(imageops::grayscale(p)),
+            ImageBgra8(ref p) => ImageLuma8(imageops::grayscale(p)),
         }
     }
 
@@ -272,37 +326,57 @@ impl DynamicImage {
     }
 
     /// Encode this image and write it to ```w```
-pub fn save<W: Writer>(&self, w: W, format: ImageFormat) -> io::IoResult<ImageResult<()>> {
+    pub fn save<W: Writer>(&self, w: W, format: ImageFormat) -> ImageResult<()> {
         let bytes = self.raw_pixels();
         let (width, height) = self.dimensions();
         let color = self.color();
 
-        let r = match format {
-            image::PNG  => {
-                let mut p = png::PNGEncoder::new(w);
-
-                try!(p.encode(bytes.as_slice(), width, height, color))
-                    Ok(())
-            }
+        match format {
+            image::PNG  => png::PNGEncoder::new(w).write_image(bytes.as_slice(), width, height, color),
+            image::PPM  => ppm::PPMEncoder::new(w).write_image(bytes.as_slice(), width, height, color),
+            image::JPEG => jpeg::JPEGEncoder::new(w).write_image(bytes.as_slice(), width, height, color),
+            image::BMP  => bmp::BMPEncoder::new(w).write_image(bytes.as_slice(), width, height, color),
+            _    => Err(image::UnsupportedError),
+        }
+    }
 
-            image::PPM  => {
-                let mut p = ppm::PPMEncoder::new(w);
+    /// Encode this image and write it to ```w```, honouring any encoder-specific
+    /// parameters carried by ```format``` (e.g. the JPEG quality level).
+    pub fn save_with_format<W: Writer>(&self, w: W, format: ImageOutputFormat) -> ImageResult<()> {
+        let bytes = self.raw_pixels();
+        let (width, height) = self.dimensions();
+        let color = self.color();
 
-                try!(p.encode(bytes.as_slice(), width, height, color))
-                    Ok(())
-            }
+        match format {
+            image::OutputPng       => png::PNGEncoder::new(w).write_image(bytes.as_slice(), width, height, color),
+            image::OutputPpm       => ppm::PPMEncoder::new(w).write_image(bytes.as_slice(), width, height, color),
+            image::OutputBmp       => bmp::BMPEncoder::new(w).write_image(bytes.as_slice(), width, height, color),
+            image::OutputJpeg(q)   => jpeg::JPEGEncoder::new_with_quality(w, q).write_image(bytes.as_slice(), width, height, color),
+        }
+    }
+}
 
-            image::JPEG => {
-                let mut j = jpeg::JPEGEncoder::new(w);
+// Adapters letting the pre-existing encoders plug into the generic save path.
+// Each wraps the encoder's `encode`, mapping its I/O error onto `IoError`.
 
-                try!(j.encode(bytes.as_slice(), width, height, color))
-                    Ok(())
-            }
+impl<W: Writer> ImageEncoder for png::PNGEncoder<W> {
+    fn write_image(self, buf: &[u8], width: u32, height: u32, color: color::ColorType) -> ImageResult<()> {
+        let mut e = self;
+        e.encode(buf, width, height, color).map_err(|_| image::IoError)
+    }
+}
 
-            _    => Err(image::UnsupportedError),
-        };
+impl<W: Writer> ImageEncoder for ppm::PPMEncoder<W> {
+    fn write_image(self, buf: &[u8], width: u32, height: u32, color: color::ColorType) -> ImageResult<()> {
+        let mut e = self;
+        e.encode(buf, width, height, color).map_err(|_| image::IoError)
+    }
+}
 
-        Ok(r)
+impl<W: Writer> ImageEncoder for jpeg::JPEGEncoder<W> {
+    fn write_image(self, buf: &[u8], width: u32, height: u32, color: color::ColorType) -> ImageResult<()> {
+        let mut e = self;
+        e.encode(buf, width, height, color).map_err(|_| image::IoError)
     }
 }
 
@@ -332,6 +406,24 @@ fn decoder_to_image<I: ImageDecoder>(codec: I) -> ImageResult<DynamicImage> {
             ImageRgba8(ImageBuf::from_pixels(p, w, h))
         }
 
+        color::BGR(8) => {
+            let p = buf.as_slice()
+                       .chunks(3)
+                       .map( | a | color::Bgr::<u8>(a[0], a[1], a[2]))
+                       .collect();
+
+            ImageBgr8(ImageBuf::from_pixels(p, w, h))
+        }
+
+        color::BGRA(8) => {
+            let p = buf.as_slice()
+                       .chunks(4)
+                       .map( | a | color::Bgra::<u8>(a[0], a[1], a[2], a[3]))
+                       .collect();
+
+            ImageBgra8(ImageBuf::from_pixels(p, w, h))
+        }
+
         color::Grey(8) => {
             let p = buf.as_slice()
                        .iter()
@@ -357,45 +449,10 @@ fn decoder_to_image<I: ImageDecoder>(codec: I) -> ImageResult<DynamicImage> {
 }
 
 fn image_to_bytes(image: &DynamicImage) -> Vec<u8> {
-    let mut r = Vec::new();
-
-    match *image {
-        //TODO: consider transmuting
-        ImageLuma8(ref a) => {
-            for & i in a.pixelbuf().iter() {
-                r.push(i.channel());
-            }
-        }
-
-        ImageLumaA8(ref a) => {
-            for & i in a.pixelbuf().iter() {
-                let (l, a) = i.channels();
-                r.push(l);
-                r.push(a);
-            }
-        }
-
-        ImageRgb8(ref a)  => {
-            for & i in a.pixelbuf().iter() {
-                let (red, g, b) = i.channels();
-                r.push(red);
-                r.push(g);
-                r.push(b);
-            }
-        }
-
-        ImageRgba8(ref a) => {
-            for & i in a.pixelbuf().iter() {
-                let (red, g, b, alpha) = i.channels();
-                r.push(red);
-                r.push(g);
-                r.push(b);
-                r.push(alpha);
-            }
-        }
-    }
-
-    r
+    // The 8-bit pixel structs are laid out as a padding-free run of their
+    // channels in declaration order, so a single bulk copy of the
+    // reinterpreted buffer yields the packed bytes.
+    dynamic_map!(*image, ref p -> p.pixelbuf().as_bytes().to_vec())
 }
 
 /// Open the image located at the path specified.
@@ -406,17 +463,7 @@ pub fn open(path: &Path) -> ImageResult<DynamicImage> {
         Err(_) => return Err(image::IoError)
     };
 
-    let ext = path.extension_str()
-                  .map_or("".to_string(), | s | s.to_ascii_lower());
-
-    let format = match ext.as_slice() {
-        "jpg" |
-        "jpeg" => image::JPEG,
-        "png"  => image::PNG,
-        "gif"  => image::GIF,
-        "webp" => image::WEBP,
-        _      => return Err(image::UnsupportedError)
-    };
+    let format = try!(ImageFormat::from_path(path));
 
     load(fin, format)
 }
@@ -428,6 +475,7 @@ pub fn load<R: Reader>(r: R, format: ImageFormat) -> ImageResult<DynamicImage> {
         image::GIF  => decoder_to_image(gif::GIFDecoder::new(r)),
         image::JPEG => decoder_to_image(jpeg::JPEGDecoder::new(r)),
         image::WEBP => decoder_to_image(webp::WebpDecoder::new(r)),
+        image::TIFF => decoder_to_image(tiff::TIFFDecoder::new(r)),
         _    => Err(image::UnsupportedError),
     }
 }
@@ -53,7 +53,105 @@ pub enum ImageFormat {
     WEBP,
 
     /// An Image in PPM Format
-    PPM
+    PPM,
+
+    /// An Image in BMP Format
+    BMP,
+
+    /// An Image in TIFF Format
+    TIFF
+}
+
+/// An output format together with any encoder-specific parameters.
+///
+/// Unlike `ImageFormat`, which only names a codec, this carries the settings
+/// the save path needs to configure an encoder — currently the JPEG quality
+/// level. `ImageFormat` values convert through `from_format`, which fills in
+/// default parameters.
+#[deriving(PartialEq, Eq, Show)]
+pub enum ImageOutputFormat {
+    /// PNG, no parameters.
+    OutputPng,
+
+    /// PPM, no parameters.
+    OutputPpm,
+
+    /// BMP, no parameters.
+    OutputBmp,
+
+    /// JPEG at the given quality (1-100).
+    OutputJpeg(u8),
+}
+
+impl ImageOutputFormat {
+    /// Convert a bare `ImageFormat` into an output format, using default
+    /// parameters where the format takes any. Unsupported formats map to
+    /// `None`.
+    pub fn from_format(format: ImageFormat) -> Option<ImageOutputFormat> {
+        match format {
+            PNG  => Some(OutputPng),
+            PPM  => Some(OutputPpm),
+            BMP  => Some(OutputBmp),
+            JPEG => Some(OutputJpeg(75)),
+            _    => None,
+        }
+    }
+}
+
+impl ImageFormat {
+    /// Determine the format from a file extension, ignoring case. Fails with
+    /// `UnsupportedError` if the extension is not recognised.
+    pub fn from_extension(ext: &str) -> ImageResult<ImageFormat> {
+        use std::ascii::StrAsciiExt;
+
+        match ext.to_ascii_lower().as_slice() {
+            "png"                 => Ok(PNG),
+            "jpg" | "jpeg"        => Ok(JPEG),
+            "gif"                 => Ok(GIF),
+            "webp"                => Ok(WEBP),
+            "ppm" | "pgm" | "pbm" => Ok(PPM),
+            "bmp"                 => Ok(BMP),
+            "tif" | "tiff"        => Ok(TIFF),
+            _                     => Err(UnsupportedError),
+        }
+    }
+
+    /// Determine the format from a path's file extension.
+    pub fn from_path(path: &Path) -> ImageResult<ImageFormat> {
+        match path.extension_str() {
+            Some(ext) => ImageFormat::from_extension(ext),
+            None      => Err(UnsupportedError),
+        }
+    }
+
+    /// Guess the format by sniffing the leading magic bytes of ```buf```.
+    pub fn guess_format(buf: &[u8]) -> ImageResult<ImageFormat> {
+        static PNG_SIG: [u8, ..8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+        if buf.len() >= 8 && buf.slice_to(8) == PNG_SIG {
+            return Ok(PNG)
+        }
+
+        if buf.len() >= 3 && buf.slice_to(3) == [0xFF, 0xD8, 0xFF] {
+            return Ok(JPEG)
+        }
+
+        if buf.len() >= 6 && (buf.slice_to(6) == "GIF87a".as_bytes()
+                           || buf.slice_to(6) == "GIF89a".as_bytes()) {
+            return Ok(GIF)
+        }
+
+        if buf.len() >= 12 && buf.slice_to(4) == "RIFF".as_bytes()
+                           && buf.slice(8, 12) == "WEBP".as_bytes() {
+            return Ok(WEBP)
+        }
+
+        if buf.len() >= 2 && buf[0] == b'P' && buf[1] >= b'1' && buf[1] <= b'6' {
+            return Ok(PPM)
+        }
+
+        Err(UnsupportedError)
+    }
 }
 
 /// The trait that all decoders implement
@@ -118,6 +216,44 @@ pub trait ImageDecoder {
     }
 }
 
+/// The trait that all encoders implement.
+///
+/// Implementors consume themselves, so an encoder wrapping a `Writer` can be
+/// passed straight to `write_image`; this lets the various `save` paths
+/// dispatch over `ImageFormat` without repeating the encoder boilerplate.
+pub trait ImageEncoder {
+    ///Encode the image `buf` of dimensions `width` x `height` and color type
+    ///`color`, writing it to the wrapped destination.
+    fn write_image(self, buf: &[u8], width: u32, height: u32, color: ColorType) -> ImageResult<()>;
+}
+
+/// Reinterpret a slice of tightly-packed pixels as raw bytes.
+///
+/// This is sound only for pixel types whose in-memory layout is a contiguous
+/// run of their channel bytes with no padding, which holds for the 8-bit
+/// `Luma`/`LumaA`/`Rgb`/`Rgba`/`Bgr`/`Bgra` value types. It lets `raw_pixels`
+/// hand out the pixel buffer without a per-channel copy.
+pub trait AsBytes {
+    /// View this pixel slice as a byte slice, without copying.
+    fn as_bytes<'a>(&'a self) -> &'a [u8];
+}
+
+impl<T: Primitive, P: Pixel<T>> AsBytes for [P] {
+    fn as_bytes<'a>(&'a self) -> &'a [u8] {
+        use std::mem;
+        use std::raw::Slice;
+
+        let len = self.len() * mem::size_of::<P>();
+
+        unsafe {
+            mem::transmute(Slice {
+                data: self.as_ptr() as *const u8,
+                len:  len,
+            })
+        }
+    }
+}
+
 /// Immutable pixel iterator
 pub struct Pixels < 'a, I> {
     image:  &'a I,
@@ -0,0 +1,295 @@
+use std::io;
+use std::io::{MemReader, SeekSet};
+
+use color;
+use lzw;
+use image;
+use image::{
+    ImageDecoder,
+    ImageResult,
+};
+
+//Baseline tags we care about
+static IMAGE_WIDTH: u16      = 256;
+static IMAGE_LENGTH: u16     = 257;
+static BITS_PER_SAMPLE: u16  = 258;
+static COMPRESSION: u16      = 259;
+static STRIP_OFFSETS: u16    = 273;
+static SAMPLES_PER_PIXEL: u16 = 277;
+static ROWS_PER_STRIP: u16   = 278;
+static STRIP_BYTE_COUNTS: u16 = 279;
+
+//Compression schemes
+static COMPRESSION_NONE: u32     = 1;
+static COMPRESSION_LZW: u32      = 5;
+static COMPRESSION_PACKBITS: u32 = 32773;
+
+/// A baseline TIFF decoder.
+///
+/// The whole stream is buffered up front so the IFD offsets can be seeked
+/// freely; strips are then concatenated in row order. Uncompressed, PackBits
+/// and LZW encoded strips are supported, which covers common baseline images.
+pub struct TIFFDecoder<R> {
+    r: R,
+
+    little_endian: bool,
+    decoded: Option<(u32, u32, color::ColorType, Vec<u8>)>,
+    scanline: u32,
+}
+
+impl<R: Reader> TIFFDecoder<R> {
+    /// Create a new decoder that decodes from the stream ```r```
+    pub fn new(r: R) -> TIFFDecoder<R> {
+        TIFFDecoder {
+            r: r,
+
+            little_endian: true,
+            decoded: None,
+            scanline: 0,
+        }
+    }
+
+    fn read_u16(&self, m: &mut MemReader) -> ImageResult<u16> {
+        let v = if self.little_endian { m.read_le_u16() } else { m.read_be_u16() };
+        v.map_err(|_| image::NotEnoughData)
+    }
+
+    fn read_u32(&self, m: &mut MemReader) -> ImageResult<u32> {
+        let v = if self.little_endian { m.read_le_u32() } else { m.read_be_u32() };
+        v.map_err(|_| image::NotEnoughData)
+    }
+
+    //Read the value of a single-count SHORT or LONG IFD entry.
+    fn entry_value(&self, m: &mut MemReader, typ: u16, valueoffset: u32) -> ImageResult<u32> {
+        //A short is left-justified in the 4-byte value field, so after the word
+        //has been byte-order corrected it sits in the low half on little-endian
+        //files and the high half on big-endian ones.
+        match typ {
+            3 if self.little_endian => Ok(valueoffset & 0xFFFF),
+            3                       => Ok(valueoffset >> 16),
+            _                       => Ok(valueoffset),
+        }
+    }
+
+    fn read_metadata(&mut self) -> ImageResult<()> {
+        let mut bytes = match self.r.read_to_end() {
+            Ok(b)  => b,
+            Err(_) => return Err(image::IoError),
+        };
+
+        let mut m = MemReader::new(bytes.clone());
+
+        let order = try!(m.read_exact(2).map_err(|_| image::NotEnoughData));
+        self.little_endian = match (order[0], order[1]) {
+            (0x49, 0x49) => true,
+            (0x4D, 0x4D) => false,
+            _            => return Err(image::FormatError),
+        };
+
+        let magic = try!(self.read_u16(&mut m));
+        if magic != 42 {
+            return Err(image::FormatError)
+        }
+
+        let ifd_offset = try!(self.read_u32(&mut m));
+        try!(m.seek(ifd_offset as i64, SeekSet).map_err(|_| image::FormatError));
+
+        let count = try!(self.read_u16(&mut m));
+
+        let mut width = 0u32;
+        let mut height = 0u32;
+        let mut bits_per_sample = 8u32;
+        let mut samples = 1u32;
+        let mut compression = COMPRESSION_NONE;
+        let mut rows_per_strip = 0u32;
+        let mut strip_offsets = Vec::new();
+        let mut strip_counts = Vec::new();
+
+        for _ in range(0, count) {
+            let tag   = try!(self.read_u16(&mut m));
+            let typ   = try!(self.read_u16(&mut m));
+            let ecount = try!(self.read_u32(&mut m));
+            let value = try!(self.read_u32(&mut m));
+
+            match tag {
+                t if t == IMAGE_WIDTH       => width  = try!(self.entry_value(&mut m, typ, value)),
+                t if t == IMAGE_LENGTH      => height = try!(self.entry_value(&mut m, typ, value)),
+                t if t == BITS_PER_SAMPLE   => bits_per_sample = try!(self.entry_value(&mut m, typ, value)),
+                t if t == SAMPLES_PER_PIXEL => samples = try!(self.entry_value(&mut m, typ, value)),
+                t if t == COMPRESSION       => compression = try!(self.entry_value(&mut m, typ, value)),
+                t if t == ROWS_PER_STRIP    => rows_per_strip = try!(self.entry_value(&mut m, typ, value)),
+                t if t == STRIP_OFFSETS     => strip_offsets = try!(self.read_offsets(&bytes, typ, ecount, value)),
+                t if t == STRIP_BYTE_COUNTS => strip_counts  = try!(self.read_offsets(&bytes, typ, ecount, value)),
+                _ => {}
+            }
+        }
+
+        if bits_per_sample != 8 {
+            return Err(image::UnsupportedColor)
+        }
+
+        let color = match samples {
+            1 => color::Grey(8),
+            3 => color::RGB(8),
+            4 => color::RGBA(8),
+            _ => return Err(image::UnsupportedColor),
+        };
+
+        if rows_per_strip == 0 {
+            rows_per_strip = height;
+        }
+
+        let rowlen = width as uint * samples as uint;
+        let mut out = Vec::with_capacity(rowlen * height as uint);
+
+        for (i, (&off, &len)) in strip_offsets.iter().zip(strip_counts.iter()).enumerate() {
+            let strip = bytes.slice(off as uint, (off + len) as uint);
+            let rows  = if (i as u32 + 1) * rows_per_strip > height {
+                height - i as u32 * rows_per_strip
+            } else {
+                rows_per_strip
+            };
+            let expected = rowlen * rows as uint;
+
+            let decoded = try!(decompress(compression, strip, expected));
+            out.push_all(decoded.as_slice());
+        }
+
+        out.truncate(rowlen * height as uint);
+
+        self.decoded = Some((width, height, color, out));
+
+        Ok(())
+    }
+
+    //Read a StripOffsets/StripByteCounts array, which is either inlined in the
+    //value field (single entry) or stored at the offset the value field points to.
+    fn read_offsets(&self, bytes: &[u8], typ: u16, ecount: u32, value: u32) -> ImageResult<Vec<u32>> {
+        if ecount == 1 {
+            //A single SHORT is left-justified in the value field just like any
+            //other inline entry, so extract it from the correct half.
+            let v = match typ {
+                3 if self.little_endian => value & 0xFFFF,
+                3                       => value >> 16,
+                _                       => value,
+            };
+            return Ok(vec![v])
+        }
+
+        let mut m = MemReader::new(bytes.slice_from(value as uint).to_vec());
+        let mut out = Vec::with_capacity(ecount as uint);
+
+        for _ in range(0, ecount) {
+            let v = match typ {
+                3 => try!(self.read_u16(&mut m)) as u32,
+                _ => try!(self.read_u32(&mut m)),
+            };
+            out.push(v);
+        }
+
+        Ok(out)
+    }
+
+    fn decode(&mut self) -> ImageResult<()> {
+        if self.decoded.is_none() {
+            try!(self.read_metadata());
+        }
+
+        Ok(())
+    }
+}
+
+//Decompress a single strip into a buffer of ```expected``` bytes.
+fn decompress(compression: u32, strip: &[u8], expected: uint) -> ImageResult<Vec<u8>> {
+    match compression {
+        c if c == COMPRESSION_NONE => Ok(strip.to_vec()),
+
+        c if c == COMPRESSION_PACKBITS => Ok(unpackbits(strip, expected)),
+
+        c if c == COMPRESSION_LZW => {
+            //TIFF packs LZW codes MSB-first, starting at a 9-bit width.
+            let mut dec = lzw::LzwReader::new(MemReader::new(strip.to_vec()), 8, true, true);
+            dec.read_to_end().map_err(|_| image::FormatError)
+        }
+
+        _ => Err(image::UnsupportedError),
+    }
+}
+
+//PackBits (Apple RLE) decoder, as used by baseline TIFF.
+fn unpackbits(strip: &[u8], expected: uint) -> Vec<u8> {
+    let mut out = Vec::with_capacity(expected);
+    let mut i = 0u;
+
+    while i < strip.len() && out.len() < expected {
+        let n = strip[i] as i8;
+        i += 1;
+
+        if n >= 0 {
+            let count = n as uint + 1;
+            for _ in range(0, count) {
+                if i >= strip.len() { break }
+                out.push(strip[i]);
+                i += 1;
+            }
+        } else if n != -128 {
+            let count = (1 - n as int) as uint;
+            if i >= strip.len() { break }
+            let b = strip[i];
+            i += 1;
+            for _ in range(0, count) {
+                out.push(b);
+            }
+        }
+    }
+
+    out
+}
+
+impl<R: Reader> ImageDecoder for TIFFDecoder<R> {
+    fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
+        let _ = try!(self.decode());
+        let (w, h, _, _) = self.decoded.get_ref().clone();
+
+        Ok((w, h))
+    }
+
+    fn colortype(&mut self) -> ImageResult<color::ColorType> {
+        let _ = try!(self.decode());
+        let (_, _, c, _) = self.decoded.get_ref().clone();
+
+        Ok(c)
+    }
+
+    fn row_len(&mut self) -> ImageResult<uint> {
+        let _ = try!(self.decode());
+        let (w, _, c, _) = self.decoded.get_ref().clone();
+
+        Ok(w as uint * color::num_components(c))
+    }
+
+    fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
+        let _ = try!(self.decode());
+        let (w, h, c, ref data) = *self.decoded.get_ref();
+
+        if self.scanline >= h {
+            return Err(image::ImageEnd)
+        }
+
+        let rowlen = w as uint * color::num_components(c);
+        let start  = self.scanline as uint * rowlen;
+
+        ::std::slice::bytes::copy_memory(buf, data.slice(start, start + rowlen));
+
+        self.scanline += 1;
+
+        Ok(self.scanline)
+    }
+
+    fn read_image(&mut self) -> ImageResult<Vec<u8>> {
+        let _ = try!(self.decode());
+        let (_, _, _, ref data) = *self.decoded.get_ref();
+
+        Ok(data.clone())
+    }
+}
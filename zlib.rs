@@ -0,0 +1,127 @@
+use std::io;
+use std::io::IoResult;
+
+use deflate::Inflater;
+
+//The largest number of bytes that can be summed into an Adler-32 accumulator
+//before `b` can overflow a u32, so we defer the modulo until then.
+static NMAX: uint = 5552;
+static ADLER_BASE: u32 = 65521;
+
+/// A zlib stream decoder.
+///
+/// zlib wraps a raw DEFLATE payload (what `Inflater` decodes) in a two-byte
+/// header and a trailing Adler-32 of the decompressed data. This reads and
+/// validates the header, delegates the payload to an `Inflater`, and checks
+/// the running checksum against the trailer once the stream ends.
+pub struct ZlibDecoder<R> {
+	inner: Inflater<R>,
+
+	header_read: bool,
+	checked: bool,
+
+	a: u32,
+	b: u32,
+	pending: uint,
+}
+
+impl<R: Reader> ZlibDecoder<R> {
+	/// Create a new decoder reading a zlib stream from ```r```.
+	pub fn new(r: R) -> ZlibDecoder<R> {
+		ZlibDecoder {
+			inner: Inflater::new(r),
+
+			header_read: false,
+			checked: false,
+
+			a: 1,
+			b: 0,
+			pending: 0,
+		}
+	}
+
+	/// Return a mutable reference to the underlying reader.
+	pub fn inner<'a>(&'a mut self) -> &'a mut R {
+		self.inner.inner()
+	}
+
+	fn read_header(&mut self) -> IoResult<()> {
+		let cmf = try!(self.inner.inner().read_u8());
+		let flg = try!(self.inner.inner().read_u8());
+
+		//FCHECK makes the header word a multiple of 31.
+		if (cmf as u16 * 256 + flg as u16) % 31 != 0 {
+			return Err(io::standard_error(io::InvalidInput))
+		}
+
+		//The compression method must be deflate (8).
+		if cmf & 0x0F != 8 {
+			return Err(io::standard_error(io::InvalidInput))
+		}
+
+		//A preset dictionary id precedes the payload when FDICT is set.
+		if flg & 0x20 != 0 {
+			let _ = try!(self.inner.inner().read_be_u32());
+		}
+
+		self.header_read = true;
+
+		Ok(())
+	}
+
+	fn update(&mut self, buf: &[u8]) {
+		for &byte in buf.iter() {
+			self.a += byte as u32;
+			self.b += self.a;
+
+			self.pending += 1;
+			if self.pending == NMAX {
+				self.a %= ADLER_BASE;
+				self.b %= ADLER_BASE;
+				self.pending = 0;
+			}
+		}
+	}
+
+	fn checksum(&self) -> u32 {
+		((self.b % ADLER_BASE) << 16) | (self.a % ADLER_BASE)
+	}
+
+	fn verify(&mut self) -> IoResult<()> {
+		let expected = try!(self.inner.inner().read_be_u32());
+
+		if expected != self.checksum() {
+			return Err(io::standard_error(io::InvalidInput))
+		}
+
+		self.checked = true;
+
+		Ok(())
+	}
+}
+
+impl<R: Reader> Reader for ZlibDecoder<R> {
+	fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+		if !self.header_read {
+			try!(self.read_header());
+		}
+
+		if self.checked {
+			return Err(io::standard_error(io::EndOfFile))
+		}
+
+		match self.inner.read(buf) {
+			Ok(n) => {
+				self.update(buf.slice_to(n));
+				Ok(n)
+			}
+
+			Err(ref e) if e.kind == io::EndOfFile => {
+				try!(self.verify());
+				Err(io::standard_error(io::EndOfFile))
+			}
+
+			Err(e) => Err(e),
+		}
+	}
+}